@@ -1,15 +1,39 @@
+use crate::entities::{escape_attribute, escape_text};
+use crate::error::ValidationError;
+use crate::event::Event;
+use crate::namespace::NamespaceContext;
+use crate::options::{CompareOptions, TextMode};
 use crate::parsetag::BaseXMLTag;
-use std::cell::RefCell;
+use crate::select;
+use std::cell::{Ref, RefCell, RefMut};
 use std::cmp::PartialEq;
 use std::collections::HashMap;
 use std::fmt::Display;
-use std::rc::Rc;
+use std::ops::Range;
+use std::rc::{Rc, Weak};
+use std::str::FromStr;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct XMLTag {
+    #[cfg_attr(feature = "serde", serde(rename = "position"))]
     pub _pos: usize,
     pub name: String,
-    pub attributes: HashMap<String, String>,
+    /// Behind a `RefCell` so parsed trees, shared through `Rc`, can still be
+    /// edited with `XMLNode::set_attribute` and friends. Read it through
+    /// `attributes()`.
+    pub(crate) attributes: RefCell<HashMap<String, String>>,
+    /// Every value, in source order, of attributes written more than once.
+    /// Only filled when parsing with `ParseOptions::attribute_multimap`,
+    /// `attributes` still holds the last value.
+    /// Read through `XMLNode::get_all_attributes`.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "repeated_is_empty"))]
+    pub(crate) repeated_attributes: RefCell<HashMap<String, Vec<String>>>,
+}
+
+#[cfg(feature = "serde")]
+fn repeated_is_empty(repeated: &RefCell<HashMap<String, Vec<String>>>) -> bool {
+    repeated.borrow().is_empty()
 }
 
 impl XMLTag {
@@ -17,27 +41,41 @@ impl XMLTag {
         Self {
             _pos,
             name,
-            attributes,
+            attributes: RefCell::new(attributes),
+            repeated_attributes: RefCell::new(HashMap::new()),
         }
     }
-    pub fn from(base: BaseXMLTag) -> Self {
-        Self {
-            _pos: base.pos,
-            name: String::from(base.name),
-            attributes: base.attribs,
-        }
+    pub(crate) fn from(base: BaseXMLTag) -> Self {
+        Self::new(base.pos, base.name, base.attribs)
+    }
+    /// Read access to the attributes. This replaces the former public
+    /// `attributes` field; the guard must be dropped before the attributes
+    /// are modified.
+    pub fn attributes(&self) -> Ref<'_, HashMap<String, String>> {
+        self.attributes.borrow()
+    }
+    /// The part of the name before the first colon, `ns` for `ns:book`.
+    pub fn prefix(&self) -> Option<&str> {
+        split_name(&self.name).0
+    }
+    /// The name without its prefix, `book` for `ns:book`.
+    pub fn local_name(&self) -> &str {
+        split_name(&self.name).1
     }
 }
 
 impl PartialEq for XMLTag {
     fn eq(&self, other: &Self) -> bool {
-        self.name == other.name && self.attributes == other.attributes && self._pos == other._pos
+        self.name == other.name
+            && self.attributes == other.attributes
+            && self.repeated_attributes == other.repeated_attributes
+            && self._pos == other._pos
     }
 }
 
 impl Display for XMLTag {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<{} {:?}>", self.name, self.attributes)
+        write!(f, "<{} {:?}>", self.name, self.attributes.borrow())
     }
 }
 
@@ -45,7 +83,20 @@ impl Display for XMLTag {
 pub struct XMLNode {
     pub tag: XMLTag,
     pub content: RefCell<String>,
+    /// Prefer `append_child` and `splice_children` to editing this directly:
+    /// a child pushed here has no place in the text and is treated as
+    /// following all of it, and removing one here lets the children after it
+    /// take over the text positions of the ones before.
     pub children: RefCell<Vec<Rc<XMLNode>>>,
+    /// Byte offset into `content` at which each child appears, used to
+    /// interleave text and children in document order. Can fall out of step
+    /// with `children` when that is edited directly, so readers go through
+    /// `child_offset` and writers through `synced_offsets`.
+    child_offsets: RefCell<Vec<usize>>,
+    /// The element this node was parsed inside of, held weakly so parent and
+    /// children do not keep each other alive.
+    parent: RefCell<Weak<XMLNode>>,
+    span: RefCell<(usize, usize)>,
 }
 
 impl XMLNode {
@@ -54,14 +105,859 @@ impl XMLNode {
             tag,
             content: RefCell::new(String::new()),
             children: RefCell::new(Vec::new()),
+            child_offsets: RefCell::new(Vec::new()),
+            parent: RefCell::new(Weak::new()),
+            span: RefCell::new((0, 0)),
         }
     }
+    /// Starts building an element named `name` in code rather than parsing
+    /// it, see `XMLNodeBuilder`.
+    ///
+    /// ```
+    /// use rxml::{XMLNode, RXML};
+    ///
+    /// let item = XMLNode::element("item").attr("id", "1").text("Dune").build();
+    /// let list = XMLNode::element("list").child(item).build();
+    ///
+    /// let parsed = RXML::new(String::from("<list><item id='1'>Dune</item></list>"));
+    /// assert!(list.structurally_eq(&parsed.parse().unwrap()));
+    /// assert_eq!(list.find_first("item").unwrap().parent().unwrap().tag.name, "list");
+    /// ```
+    pub fn element(name: &str) -> XMLNodeBuilder {
+        XMLNodeBuilder {
+            node: XMLNode::new(XMLTag::new(0, String::from(name), HashMap::new())),
+        }
+    }
+    /// The namespace URI of this element, the one its prefix, or the default
+    /// namespace when it has none, is bound to here or on the closest
+    /// ancestor declaring it.
+    pub fn namespace(&self) -> Option<String> {
+        self.lookup_namespace(self.tag.prefix())
+    }
+    /// The URI `prefix` is bound to in scope of this node, `None` standing for
+    /// the default namespace. Also resolves the prefixes of attribute names,
+    /// keeping in mind unprefixed attributes are in no namespace at all. An
+    /// empty `xmlns=""` undeclares the default namespace. Ancestors are only
+    /// reachable from parsed nodes, see `parent`.
+    pub fn lookup_namespace(&self, prefix: Option<&str>) -> Option<String> {
+        self.namespace_context().resolve(prefix).map(String::from)
+    }
+    /// Every namespace declaration in scope of this node, made here or on an
+    /// ancestor, the nearest declaration of a prefix taking precedence.
+    pub fn namespace_context(&self) -> NamespaceContext {
+        let mut context = NamespaceContext::default();
+        context.declare_missing(&self.attribute_map_ref());
+        for ancestor in self.ancestors() {
+            context.declare_missing(&ancestor.attribute_map_ref());
+        }
+        context
+    }
+    /// The element this node was parsed or inserted inside of. `None` for the
+    /// root, once the parent has been dropped, and for nodes attached by
+    /// `append_child` or by pushing onto `children`, as those only get to
+    /// borrow the parent.
+    pub fn parent(&self) -> Option<Rc<XMLNode>> {
+        self.parent.borrow().upgrade()
+    }
+    /// The parent, its parent and so on up to the root, nearest first. Stops
+    /// early wherever a node cannot reach its parent, see `parent`.
+    pub fn ancestors(&self) -> impl Iterator<Item = Rc<XMLNode>> {
+        let mut next = self.parent();
+        std::iter::from_fn(move || {
+            let node = next.take()?;
+            next = node.parent();
+            Some(node)
+        })
+    }
+    /// The nearest ancestor named `name`, e.g. the `table` a `td` sits in.
+    pub fn parent_named(&self, name: &str) -> Option<Rc<XMLNode>> {
+        self.ancestors().find(|node| node.tag.name == name)
+    }
+    pub(crate) fn set_parent(&self, parent: &Rc<XMLNode>) {
+        *self.parent.borrow_mut() = Rc::downgrade(parent);
+    }
+    /// Byte offsets of this element in the parsed source, from the `<` of its
+    /// opening tag up to just past the `>` of its closing tag, so
+    /// `&source[start..end]` is the element as written. `(0, 0)` for nodes
+    /// not created by the parser.
+    pub fn span(&self) -> (usize, usize) {
+        *self.span.borrow()
+    }
+    pub(crate) fn set_span(&self, span: (usize, usize)) {
+        *self.span.borrow_mut() = span;
+    }
     pub fn append_child(&self, child: Rc<XMLNode>) {
+        let mut offsets = self.synced_offsets();
+        offsets.push(self.content.borrow().len());
         self.children.borrow_mut().push(child);
     }
+    pub fn append_children(&self, children: impl IntoIterator<Item = Rc<XMLNode>>) {
+        for child in children {
+            self.append_child(child);
+        }
+    }
+    /// A snapshot of the children taken at call time. The tree can be freely
+    /// modified while iterating over it.
+    pub fn cloned_children(&self) -> Vec<Rc<XMLNode>> {
+        self.children.borrow().clone()
+    }
+    /// The direct children in order, iterating over a snapshot like
+    /// `cloned_children`.
+    pub fn child_nodes(&self) -> impl Iterator<Item = Rc<XMLNode>> {
+        self.cloned_children().into_iter()
+    }
+    /// The first direct child named `name`. Unlike `find_first` it does not
+    /// look any deeper.
+    pub fn child(&self, name: &str) -> Option<Rc<XMLNode>> {
+        self.children
+            .borrow()
+            .iter()
+            .find(|child| child.tag.name == name)
+            .cloned()
+    }
+    pub fn child_count(&self) -> usize {
+        self.children.borrow().len()
+    }
+    pub fn has_children(&self) -> bool {
+        !self.children.borrow().is_empty()
+    }
+    /// Position of `child` among this node's children, compared by identity
+    /// rather than by value.
+    pub fn child_index(&self, child: &Rc<XMLNode>) -> Option<usize> {
+        self.children
+            .borrow()
+            .iter()
+            .position(|c| Rc::ptr_eq(c, child))
+    }
+    /// Replaces the children in `range` with `replacement` like `Vec::splice`,
+    /// returning the removed children. A range reaching past the end is
+    /// clamped to the existing children. Replacements take the text position
+    /// of the first child they replace and report this node as their parent,
+    /// while the removed children no longer report one.
+    pub fn splice_children(
+        self: &Rc<Self>,
+        range: Range<usize>,
+        replacement: Vec<Rc<XMLNode>>,
+    ) -> Vec<Rc<XMLNode>> {
+        let mut offsets = self.synced_offsets();
+        let mut children = self.children.borrow_mut();
+        let content_len = self.content.borrow().len();
+
+        let end = range.end.min(children.len());
+        let start = range.start.min(end);
+        let offset = offsets.get(start).copied().unwrap_or(content_len);
+
+        offsets.splice(start..end, std::iter::repeat_n(offset, replacement.len()));
+        for child in replacement.iter() {
+            child.set_parent(self);
+        }
+        let removed: Vec<Rc<XMLNode>> = children.splice(start..end, replacement).collect();
+        for child in removed.iter() {
+            *child.parent.borrow_mut() = Weak::new();
+        }
+        removed
+    }
+    /// Inserts `child` so it ends up at `index`, right before the text position
+    /// of the child it displaces. An index past the end appends.
+    pub fn insert_child(self: &Rc<Self>, index: usize, child: Rc<XMLNode>) {
+        self.splice_children(index..index, vec![child]);
+    }
+    /// Removes and returns the child at `index`, `None` when there is none.
+    pub fn remove_child(self: &Rc<Self>, index: usize) -> Option<Rc<XMLNode>> {
+        if index >= self.child_count() {
+            return None;
+        }
+        self.splice_children(index..index + 1, Vec::new()).pop()
+    }
+    /// Removes every descendant for which `predicate` holds along with its
+    /// subtree. This node itself is never tested. Works iteratively so deep
+    /// trees can be pruned.
+    pub fn prune_if(&self, predicate: impl Fn(&XMLNode) -> bool) {
+        let mut pending = self.prune_children(&predicate);
+        while let Some(node) = pending.pop() {
+            pending.extend(node.prune_children(&predicate));
+        }
+    }
+    /// Drops the children matching `predicate`, returning the ones kept. The
+    /// dropped children no longer report a parent. `predicate` runs before
+    /// anything is changed, so it may look around the tree.
+    fn prune_children(&self, predicate: &impl Fn(&XMLNode) -> bool) -> Vec<Rc<XMLNode>> {
+        let verdicts: Vec<bool> = self
+            .cloned_children()
+            .iter()
+            .map(|child| predicate(child))
+            .collect();
+
+        let mut offsets = self.synced_offsets();
+        let mut children = self.children.borrow_mut();
+        let mut index = 0;
+        for prune in verdicts {
+            if prune {
+                *children.remove(index).parent.borrow_mut() = Weak::new();
+                offsets.remove(index);
+            } else {
+                index += 1;
+            }
+        }
+        children.clone()
+    }
     pub fn push_content(&self, content: &str) {
         self.content.borrow_mut().push_str(content);
     }
+    /// Length in bytes of this node's own text, ignoring descendants.
+    pub fn content_len(&self) -> usize {
+        self.content.borrow().len()
+    }
+    pub fn attribute_count(&self) -> usize {
+        self.tag.attributes().len()
+    }
+    /// Whether this node's own text, ignoring descendants, is empty or only
+    /// whitespace.
+    pub fn is_whitespace_only(&self) -> bool {
+        self.content.borrow().trim().is_empty()
+    }
+    /// Read access to this node's attributes without cloning them. The guard
+    /// must be dropped before the attributes are modified.
+    pub fn attribute_map_ref(&self) -> Ref<'_, HashMap<String, String>> {
+        self.tag.attributes()
+    }
+    /// The value of attribute `key`, borrowed from the node like
+    /// `attribute_map_ref`.
+    pub fn attr(&self, key: &str) -> Option<Ref<'_, str>> {
+        Ref::filter_map(self.attribute_map_ref(), |attributes| {
+            attributes.get(key).map(String::as_str)
+        })
+        .ok()
+    }
+    pub fn has_attr(&self, key: &str) -> bool {
+        self.attribute_map_ref().contains_key(key)
+    }
+    /// Attribute `key` parsed as a `T`, `None` when it is missing, e.g.
+    /// `node.attr_parse::<u32>("age")`.
+    pub fn attr_parse<T: FromStr>(&self, key: &str) -> Option<Result<T, T::Err>> {
+        self.attribute_map_ref().get(key).map(|value| value.parse())
+    }
+    /// The value of attribute `key`, or `default` when it is missing. The
+    /// default is `'static` rather than borrowed for as long as the node, as
+    /// the result borrows the attributes through a `Ref`.
+    pub fn attr_or(&self, key: &str, default: &'static str) -> Ref<'_, str> {
+        Ref::map(self.attribute_map_ref(), |attributes| {
+            attributes.get(key).map_or(default, String::as_str)
+        })
+    }
+    /// Sets an attribute, returning the value it replaced if there was one.
+    pub fn set_attribute(&self, name: &str, value: &str) -> Option<String> {
+        self.tag.repeated_attributes.borrow_mut().remove(name);
+        self.tag
+            .attributes
+            .borrow_mut()
+            .insert(String::from(name), String::from(value))
+    }
+    /// Sets the attribute to `default` unless it is already present. Returns
+    /// whether it was added.
+    pub fn ensure_attribute(&self, name: &str, default: &str) -> bool {
+        let mut attributes = self.tag.attributes.borrow_mut();
+        if attributes.contains_key(name) {
+            return false;
+        }
+        attributes.insert(String::from(name), String::from(default));
+        true
+    }
+    /// Like `set_attribute` but refuses names that are not valid XML names,
+    /// which could not be written back out as a well-formed document.
+    pub fn set_attribute_checked(&self, name: &str, value: &str) -> Result<(), ValidationError> {
+        if !is_valid_name(name) {
+            return Err(ValidationError::InvalidName(String::from(name)));
+        }
+        self.set_attribute(name, value);
+        Ok(())
+    }
+    pub fn remove_attribute(&self, name: &str) -> Option<String> {
+        self.tag.repeated_attributes.borrow_mut().remove(name);
+        self.tag.attributes.borrow_mut().remove(name)
+    }
+    /// Every value of attribute `name` in source order. Holds more than one
+    /// value only for repeated attributes parsed with
+    /// `ParseOptions::attribute_multimap`. The guard must be dropped before
+    /// the attributes are modified.
+    pub fn get_all_attributes(&self, name: &str) -> Ref<'_, [String]> {
+        let repeated = Ref::filter_map(self.tag.repeated_attributes.borrow(), |repeated| {
+            repeated.get(name).map(Vec::as_slice)
+        });
+        match repeated {
+            Ok(values) => values,
+            Err(_) => Ref::map(self.attribute_map_ref(), |attributes| {
+                attributes.get(name).map_or(&[][..], std::slice::from_ref)
+            }),
+        }
+    }
+    /// Moves the value of attribute `old` to `new`, overwriting `new` if it is
+    /// already present. Returns whether `old` existed.
+    pub fn rename_attribute(&self, old: &str, new: &str) -> bool {
+        let mut attributes = self.tag.attributes.borrow_mut();
+        match attributes.remove(old) {
+            Some(value) => {
+                attributes.insert(String::from(new), value);
+                let mut repeated = self.tag.repeated_attributes.borrow_mut();
+                repeated.remove(new);
+                if let Some(values) = repeated.remove(old) {
+                    repeated.insert(String::from(new), values);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+    /// The attributes whose name starts with `prefix`, like `data-`, sorted by
+    /// name.
+    pub fn attributes_with_prefix(&self, prefix: &str) -> Vec<(String, String)> {
+        let mut matching: Vec<(String, String)> = self
+            .attribute_map_ref()
+            .iter()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        matching.sort();
+        matching
+    }
+    /// Replaces every attribute of this node with what `f` maps its name and
+    /// value to, dropping it when `f` returns `None`. When two attributes map
+    /// to the same name either may win. Repeated values kept by
+    /// `ParseOptions::attribute_multimap` are discarded.
+    pub fn map_attributes(&self, f: impl Fn(&str, &str) -> Option<(String, String)>) {
+        let mut attributes = self.tag.attributes.borrow_mut();
+        let mapped: HashMap<String, String> = attributes
+            .iter()
+            .filter_map(|(name, value)| f(name, value))
+            .collect();
+        *attributes = mapped;
+        self.tag.repeated_attributes.borrow_mut().clear();
+    }
+    /// Calls `f` with the owning node, name and value of every attribute in
+    /// this subtree, starting with this node's own attributes.
+    pub fn for_each_attribute(&self, mut f: impl FnMut(&XMLNode, &str, &str)) {
+        self.walk(|node, _| {
+            for (name, value) in node.attribute_map_ref().iter() {
+                f(node, name, value);
+            }
+        });
+    }
+    /// Up to `limit` descendants named `name` in document order, stopping the
+    /// search as soon as enough are found. This node itself is not a
+    /// candidate, only what is below it.
+    pub fn find_n(&self, name: &str, limit: usize) -> Vec<Rc<XMLNode>> {
+        let mut found: Vec<Rc<XMLNode>> = Vec::new();
+        let mut stack: Vec<Rc<XMLNode>> = self.children.borrow().iter().rev().cloned().collect();
+
+        while found.len() < limit {
+            let node = match stack.pop() {
+                Some(node) => node,
+                None => break,
+            };
+            stack.extend(node.children.borrow().iter().rev().cloned());
+            if node.tag.name == name {
+                found.push(node);
+            }
+        }
+        found
+    }
+    /// The first node named `name` in document order, this node itself when
+    /// its name matches, otherwise the first such descendant.
+    pub fn find_first(self: &Rc<Self>, name: &str) -> Option<Rc<XMLNode>> {
+        match self.tag.name == name {
+            true => Some(Rc::clone(self)),
+            false => self.find_n(name, 1).pop(),
+        }
+    }
+    /// Every node named `name` in document order, starting with this node
+    /// itself when its name matches, followed by the matching descendants.
+    pub fn find_all(self: &Rc<Self>, name: &str) -> Vec<Rc<XMLNode>> {
+        let mut found: Vec<Rc<XMLNode>> = Vec::new();
+        if self.tag.name == name {
+            found.push(Rc::clone(self));
+        }
+        found.extend(self.find_n(name, usize::MAX));
+        found
+    }
+    /// Every descendant in document order whose attribute `key` is exactly
+    /// `value`, restricted to elements named `name` when given, e.g.
+    /// `find_by_attr(Some("div"), "id", "main")`.
+    pub fn find_by_attr(&self, name: Option<&str>, key: &str, value: &str) -> Vec<Rc<XMLNode>> {
+        self.descendants()
+            .filter(|node| name.is_none_or(|name| node.tag.name == name))
+            .filter(|node| {
+                node.attribute_map_ref()
+                    .get(key)
+                    .is_some_and(|v| v == value)
+            })
+            .collect()
+    }
+    /// Descendants matching a simple CSS-like selector, in document order:
+    /// `tag`, `tag.class` matching one of the whitespace separated tokens of
+    /// the `class` attribute, `#id`, and any of those combined like
+    /// `item.sale#first`. Separating them with spaces, as in `shop item`, asks
+    /// for descendants of descendants. Unlike `find_all` only elements below
+    /// this node match, on either side of a space. A malformed selector
+    /// matches nothing.
+    pub fn select(&self, selector: &str) -> Vec<Rc<XMLNode>> {
+        let compounds = match select::parse(selector) {
+            Some(compounds) => compounds,
+            None => return Vec::new(),
+        };
+        let last = compounds.len() - 1;
+        let mut found: Vec<Rc<XMLNode>> = Vec::new();
+        // Each node is paired with how many leading compounds its ancestors
+        // matched. Matching greedily is enough for the descendant combinator.
+        let mut stack: Vec<(Rc<XMLNode>, usize)> = self
+            .children
+            .borrow()
+            .iter()
+            .rev()
+            .map(|child| (Rc::clone(child), 0))
+            .collect();
+
+        while let Some((node, matched)) = stack.pop() {
+            let mut below = matched;
+            if matched == last && compounds[last].matches(&node) {
+                found.push(Rc::clone(&node));
+            } else if matched < last && compounds[matched].matches(&node) {
+                below += 1;
+            }
+            stack.extend(
+                node.children
+                    .borrow()
+                    .iter()
+                    .rev()
+                    .map(|child| (Rc::clone(child), below)),
+            );
+        }
+        found
+    }
+    /// Like `find_n` without a limit, pairing each match with its path from
+    /// this node, e.g. `/root/section[2]/error`. A step carries its 1-based position among
+    /// the siblings of the same name whenever there is more than one.
+    pub fn find_all_with_path(&self, name: &str) -> Vec<(String, Rc<XMLNode>)> {
+        let mut found: Vec<(String, Rc<XMLNode>)> = Vec::new();
+        let mut stack: Vec<(String, Rc<XMLNode>)> =
+            child_paths(self, &format!("/{}", self.tag.name));
+        stack.reverse();
+
+        while let Some((path, node)) = stack.pop() {
+            let mut children = child_paths(&node, &path);
+            children.reverse();
+            stack.extend(children);
+            if node.tag.name == name {
+                found.push((path, node));
+            }
+        }
+        found
+    }
+    /// Number of edges on the longest path down to a leaf, 0 for a leaf.
+    /// Works iteratively so deep trees can be measured.
+    pub fn height(&self) -> usize {
+        let mut height = 0;
+        let mut stack: Vec<(Rc<XMLNode>, usize)> = self
+            .children
+            .borrow()
+            .iter()
+            .map(|child| (Rc::clone(child), 1))
+            .collect();
+        while let Some((node, depth)) = stack.pop() {
+            height = height.max(depth);
+            stack.extend(
+                node.children
+                    .borrow()
+                    .iter()
+                    .map(|child| (Rc::clone(child), depth + 1)),
+            );
+        }
+        height
+    }
+    /// Number of elements below this node, 0 for a leaf. Walks the subtree on
+    /// every call, nothing is cached.
+    pub fn descendant_count(&self) -> usize {
+        self.descendants().count()
+    }
+    /// Every element below this node in document (pre-)order, not including
+    /// this node. Lazy: a node's children are only looked at once the node
+    /// has been yielded.
+    pub fn descendants(&self) -> impl Iterator<Item = Rc<XMLNode>> {
+        let mut stack: Vec<Rc<XMLNode>> = self.children.borrow().iter().rev().cloned().collect();
+
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children.borrow().iter().rev().cloned());
+            Some(node)
+        })
+    }
+    /// Calls `f` on this node and every element below it in document
+    /// (pre-)order, with its depth below this node, 0 for this node. Unlike
+    /// `descendants` nothing is collected, and `f` only gets a reference.
+    /// Works iteratively so deep trees can be walked.
+    pub fn walk<F: FnMut(&XMLNode, usize)>(&self, mut f: F) {
+        f(self, 0);
+        let mut stack: Vec<(Rc<XMLNode>, usize)> = self
+            .children
+            .borrow()
+            .iter()
+            .rev()
+            .map(|child| (Rc::clone(child), 1))
+            .collect();
+        while let Some((node, depth)) = stack.pop() {
+            f(&node, depth);
+            stack.extend(
+                node.children
+                    .borrow()
+                    .iter()
+                    .rev()
+                    .map(|child| (Rc::clone(child), depth + 1)),
+            );
+        }
+    }
+    /// Number of nodes in this subtree without children, 1 for a leaf.
+    pub fn leaf_count(&self) -> usize {
+        if !self.has_children() {
+            return 1;
+        }
+        self.descendants()
+            .filter(|node| !node.has_children())
+            .count()
+    }
+    /// A copy of the whole subtree sharing no nodes with the original, unlike
+    /// `clone` which shares the children. Every copied descendant reports its
+    /// copied parent, while the copy itself has none. Works iteratively so
+    /// deep trees can be copied.
+    pub fn deep_clone(&self) -> Rc<XMLNode> {
+        let root = Rc::new(self.clone_without_children());
+        let mut pending: Vec<(Rc<XMLNode>, Rc<XMLNode>)> =
+            vec![(Rc::new(self.clone()), Rc::clone(&root))];
+
+        while let Some((original, copy)) = pending.pop() {
+            for child in original.children.borrow().iter() {
+                let child_copy = Rc::new(child.clone_without_children());
+                child_copy.set_parent(&copy);
+                copy.children.borrow_mut().push(Rc::clone(&child_copy));
+                pending.push((Rc::clone(child), child_copy));
+            }
+        }
+        root
+    }
+    fn clone_without_children(&self) -> XMLNode {
+        XMLNode {
+            tag: self.tag.clone(),
+            content: self.content.clone(),
+            children: RefCell::new(Vec::new()),
+            child_offsets: self.child_offsets.clone(),
+            parent: RefCell::new(Weak::new()),
+            span: self.span.clone(),
+        }
+    }
+    /// Like `==` but ignoring where in the source each element was found, so
+    /// the same tree parsed from differently laid out documents, or built in
+    /// code, compares equal. Iterative like `==`.
+    pub fn structurally_eq(&self, other: &XMLNode) -> bool {
+        let mut pairs: Vec<(Rc<XMLNode>, Rc<XMLNode>)> = Vec::new();
+        if !self.shallow_eq(other, &mut pairs, false) {
+            return false;
+        }
+        while let Some((left, right)) = pairs.pop() {
+            if !left.shallow_eq(&right, &mut pairs, false) {
+                return false;
+            }
+        }
+        true
+    }
+    /// Compares everything but the children's contents, queueing the child
+    /// pairs in `pairs` for the caller to compare. Tag positions are only
+    /// compared when `positions` is set.
+    fn shallow_eq(
+        &self,
+        other: &XMLNode,
+        pairs: &mut Vec<(Rc<XMLNode>, Rc<XMLNode>)>,
+        positions: bool,
+    ) -> bool {
+        let (children, other_children) = (self.children.borrow(), other.children.borrow());
+        let tags_equal = match positions {
+            true => self.tag == other.tag,
+            false => {
+                self.tag.name == other.tag.name
+                    && self.tag.attributes == other.tag.attributes
+                    && self.tag.repeated_attributes == other.tag.repeated_attributes
+            }
+        };
+        if !tags_equal
+            || children.len() != other_children.len()
+            || self.text_runs() != other.text_runs()
+        {
+            return false;
+        }
+        pairs.extend(
+            children
+                .iter()
+                .zip(other_children.iter())
+                .map(|(left, right)| (Rc::clone(left), Rc::clone(right))),
+        );
+        true
+    }
+    /// Compares both subtrees, disregarding the differences `options` allows.
+    /// Source positions are never compared. Iterative like `==`.
+    pub fn equals_ignoring(&self, other: &XMLNode, options: &CompareOptions) -> bool {
+        if options.element_order {
+            let mut classes: HashMap<SubtreeKey, usize> = HashMap::new();
+            return subtree_class(self, options, &mut classes)
+                == subtree_class(other, options, &mut classes);
+        }
+        let mut pairs: Vec<(Rc<XMLNode>, Rc<XMLNode>)> = Vec::new();
+        if !self.shallow_eq_ignoring(other, options, &mut pairs) {
+            return false;
+        }
+        while let Some((left, right)) = pairs.pop() {
+            if !left.shallow_eq_ignoring(&right, options, &mut pairs) {
+                return false;
+            }
+        }
+        true
+    }
+    /// Like `shallow_eq`, comparing as `equals_ignoring` does with children
+    /// in order.
+    fn shallow_eq_ignoring(
+        &self,
+        other: &XMLNode,
+        options: &CompareOptions,
+        pairs: &mut Vec<(Rc<XMLNode>, Rc<XMLNode>)>,
+    ) -> bool {
+        let (children, other_children) = (self.children.borrow(), other.children.borrow());
+        if self.tag.name != other.tag.name
+            || *self.attribute_map_ref() != *other.attribute_map_ref()
+            || compared_text(&self.content.borrow(), options)
+                != compared_text(&other.content.borrow(), options)
+            || children.len() != other_children.len()
+        {
+            return false;
+        }
+        pairs.extend(
+            children
+                .iter()
+                .zip(other_children.iter())
+                .map(|(left, right)| (Rc::clone(left), Rc::clone(right))),
+        );
+        true
+    }
+    /// The child offsets, first brought back in step with `children`.
+    /// Children pushed onto `children` directly get the end of the text,
+    /// where they are currently displayed, and offsets left over from
+    /// children removed directly are dropped.
+    fn synced_offsets(&self) -> RefMut<'_, Vec<usize>> {
+        let mut offsets = self.child_offsets.borrow_mut();
+        offsets.resize(self.children.borrow().len(), self.content.borrow().len());
+        offsets
+    }
+    /// Offset into `content` at which child `index` appears. Children added by
+    /// mutating `children` directly have no recorded offset and are treated as
+    /// following all of the text.
+    fn child_offset(&self, index: usize, content: &str, min: usize) -> usize {
+        match self.child_offsets.borrow().get(index) {
+            Some(&offset) if offset >= min && content.is_char_boundary(offset) => offset,
+            _ => content.len(),
+        }
+    }
+    /// Calls `f` with every text run in this subtree in document order.
+    fn visit_text(&self, f: &mut impl FnMut(&str)) {
+        let content = self.content.borrow();
+        let mut start = 0;
+        for (index, child) in self.children.borrow().iter().enumerate() {
+            let end = self.child_offset(index, &content, start);
+            f(&content[start..end]);
+            child.visit_text(f);
+            start = end;
+        }
+        f(&content[start..]);
+    }
+    /// This node's own text with leading and trailing whitespace trimmed,
+    /// borrowed from the node. Holds the text around every child, not that of
+    /// descendants.
+    pub fn get_text(&self) -> Ref<'_, str> {
+        Ref::map(self.content.borrow(), |content| content.trim())
+    }
+    /// The text leading this node's content, up to its first child. `None`
+    /// when the node starts with an element or the leading text is only
+    /// whitespace.
+    pub fn first_text(&self) -> Option<Ref<'_, str>> {
+        let end = match self.children.borrow().is_empty() {
+            true => self.content.borrow().len(),
+            false => self.child_offset(0, &self.content.borrow(), 0),
+        };
+        Ref::filter_map(self.content.borrow(), |content| {
+            Some(&content[..end]).filter(|text| !text.trim().is_empty())
+        })
+        .ok()
+    }
+    /// All text in this subtree concatenated in document order, exactly as
+    /// stored.
+    pub fn text_content(&self) -> String {
+        let mut text = String::new();
+        self.collect_text_into(&mut text);
+        text
+    }
+    /// Appends the `text_content` of this subtree to `buf`, letting callers
+    /// reuse one buffer across many nodes.
+    pub fn collect_text_into(&self, buf: &mut String) {
+        self.visit_text(&mut |run| buf.push_str(run));
+    }
+    /// The `text_content` of both nodes, or `None` when they are equal.
+    pub fn diff_text(&self, other: &XMLNode) -> Option<(String, String)> {
+        let (mine, theirs) = (self.text_content(), other.text_content());
+        if mine == theirs {
+            return None;
+        }
+        Some((mine, theirs))
+    }
+    /// A readable plain text view of this subtree. Every element boundary is
+    /// treated as a word break: text runs are trimmed, whitespace inside them
+    /// is collapsed to a single space and non-empty runs are joined with a
+    /// single space.
+    pub fn strip_tags(&self) -> String {
+        let mut words: Vec<String> = Vec::new();
+        self.visit_text(&mut |run| words.extend(run.split_whitespace().map(String::from)));
+        words.join(" ")
+    }
+    /// All text of this subtree in document order, like `text_content` but
+    /// with a single space added where two text runs would otherwise touch
+    /// without whitespace between them, so `<p>a<b>b</b></p>` reads `a b`.
+    /// Whitespace already present is kept as is and empty runs are skipped.
+    pub fn text_recursive(&self) -> String {
+        let mut text = String::new();
+        self.visit_text(&mut |run| {
+            let touching = !text.is_empty()
+                && !text.ends_with(char::is_whitespace)
+                && !run.starts_with(char::is_whitespace);
+            if touching && !run.is_empty() {
+                text.push(' ');
+            }
+            text.push_str(run);
+        });
+        text
+    }
+    /// A canonical serialization of this subtree for hashing and comparison,
+    /// equal for documents that differ only in formatting:
+    ///
+    /// - no declaration and nothing between elements but their text;
+    /// - attributes sorted by name, written as ` name="value"`;
+    /// - every element written as a start and end tag, never self-closing;
+    /// - every text run trimmed, inner whitespace runs collapsed to a single
+    ///   space and empty runs dropped;
+    /// - `&`, `<` and `>` escaped in text, and `"` too in attribute values.
+    pub fn canonicalize(&self) -> String {
+        let mut out = String::new();
+        for event in self.to_events() {
+            match event {
+                Event::StartElement { name, attributes } => {
+                    let mut attributes: Vec<(String, String)> = attributes.into_iter().collect();
+                    attributes.sort();
+                    out.push('<');
+                    out.push_str(&name);
+                    for (key, value) in attributes {
+                        out.push_str(&format!(" {}=\"{}\"", key, escape_attribute(&value)));
+                    }
+                    out.push('>');
+                }
+                Event::EndElement { name } => out.push_str(&format!("</{}>", name)),
+                Event::Text(text) => {
+                    let words: Vec<&str> = text.split_whitespace().collect();
+                    out.push_str(&escape_text(&words.join(" ")));
+                }
+                Event::Eof => {}
+            }
+        }
+        out
+    }
+    /// Flattens this subtree into the events a reader would produce for it,
+    /// with text interleaved between children in document order. No
+    /// `Event::Eof` is emitted.
+    pub fn to_events(&self) -> Vec<Event> {
+        let mut events: Vec<Event> = Vec::new();
+        self.push_events(&mut events);
+        events
+    }
+    fn push_events(&self, events: &mut Vec<Event>) {
+        events.push(Event::StartElement {
+            name: self.tag.name.to_owned(),
+            attributes: self.attribute_map_ref().clone(),
+        });
+
+        let content = self.content.borrow();
+        let mut start = 0;
+        for (index, child) in self.children.borrow().iter().enumerate() {
+            let end = self.child_offset(index, &content, start);
+            if end > start {
+                events.push(Event::Text(String::from(&content[start..end])));
+            }
+            child.push_events(events);
+            start = end;
+        }
+        if start < content.len() {
+            events.push(Event::Text(String::from(&content[start..])));
+        }
+
+        events.push(Event::EndElement {
+            name: self.tag.name.to_owned(),
+        });
+    }
+    /// This node's own text split at its children: one more run than there
+    /// are children, run `i` coming right before child `i`.
+    pub(crate) fn text_runs(&self) -> Vec<String> {
+        let content = self.content.borrow();
+        let mut runs: Vec<String> = Vec::new();
+        let mut start = 0;
+        for index in 0..self.children.borrow().len() {
+            let end = self.child_offset(index, &content, start);
+            runs.push(String::from(&content[start..end]));
+            start = end;
+        }
+        runs.push(String::from(&content[start..]));
+        runs
+    }
+    /// Rewrites this node's own text according to `mode`. Trimming applies to
+    /// the text as a whole, that is the start of the first run and the end of
+    /// the last, and every child keeps its place between the runs.
+    pub(crate) fn apply_text_mode(&self, mode: TextMode) {
+        if mode == TextMode::Preserve {
+            return;
+        }
+        let mut runs = self.text_runs();
+        if mode == TextMode::Collapse {
+            for run in runs.iter_mut() {
+                *run = collapse_whitespace(run);
+            }
+        }
+        let last = runs.len() - 1;
+        runs[0] = String::from(runs[0].trim_start());
+        runs[last] = String::from(runs[last].trim_end());
+
+        let mut content = self.content.borrow_mut();
+        let mut offsets = self.child_offsets.borrow_mut();
+        content.clear();
+        offsets.clear();
+        for run in &runs[..last] {
+            content.push_str(run);
+            offsets.push(content.len());
+        }
+        content.push_str(&runs[last]);
+    }
+    /// Every node of this subtree in document (pre-)order paired with its
+    /// depth, this node being at depth 0. The root is yielded as a shallow
+    /// clone sharing its children with `self`.
+    pub fn iter_pairs(&self) -> impl Iterator<Item = (usize, Rc<XMLNode>)> {
+        let mut node_stack: Vec<(usize, Rc<XMLNode>)> = vec![(0, Rc::new(self.to_owned()))];
+
+        std::iter::from_fn(move || {
+            let (depth, top) = node_stack.pop()?;
+            for child in top.children.borrow().iter().rev() {
+                node_stack.push((depth + 1, Rc::clone(child)));
+            }
+            Some((depth, top))
+        })
+    }
     fn pretty_format(&self) -> String {
         let mut out_string = String::new();
         let mut node_stack: Vec<(usize, Rc<XMLNode>)> = Vec::new();
@@ -80,21 +976,14 @@ impl XMLNode {
             };
 
             for _ in 0..depth {
-                prefix.push_str(" ")
+                prefix.push(' ')
             }
 
-            let suffix: String;
-
-            if !top.content.borrow().is_empty() {
-                suffix = format!(
-                    "{}{} '{}'\n",
-                    prefix,
-                    top.tag.to_string(),
-                    top.content.borrow()
-                );
+            let suffix = if !top.content.borrow().is_empty() {
+                format!("{}{} '{}'\n", prefix, top.tag, top.content.borrow())
             } else {
-                suffix = format!("{}{}\n", prefix, top.tag.to_string());
-            }
+                format!("{}{}\n", prefix, top.tag)
+            };
 
             out_string.push_str(&suffix);
 
@@ -106,9 +995,174 @@ impl XMLNode {
     }
 }
 
+/// The children of `node` with their paths, `parent_path` being the path of
+/// `node` itself.
+fn child_paths(node: &XMLNode, parent_path: &str) -> Vec<(String, Rc<XMLNode>)> {
+    let children = node.children.borrow();
+    let mut totals: HashMap<&str, usize> = HashMap::new();
+    for child in children.iter() {
+        *totals.entry(child.tag.name.as_str()).or_default() += 1;
+    }
+
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    children
+        .iter()
+        .map(|child| {
+            let name = child.tag.name.as_str();
+            let position = seen.entry(name).or_default();
+            *position += 1;
+            let path = match totals[name] {
+                1 => format!("{}/{}", parent_path, name),
+                _ => format!("{}/{}[{}]", parent_path, name, position),
+            };
+            (path, Rc::clone(child))
+        })
+        .collect()
+}
+
+/// Splits a name at its first colon into prefix and local name.
+fn split_name(name: &str) -> (Option<&str>, &str) {
+    match name.split_once(':') {
+        Some((prefix, local_name)) => (Some(prefix), local_name),
+        None => (None, name),
+    }
+}
+
+/// `text` with every run of whitespace replaced by a single space.
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut in_whitespace = false;
+    for c in text.chars() {
+        if !c.is_whitespace() {
+            collapsed.push(c);
+        } else if !in_whitespace {
+            collapsed.push(' ');
+        }
+        in_whitespace = c.is_whitespace();
+    }
+    collapsed
+}
+
+/// A subtree as `equals_ignoring` sees it: name, sorted attributes, compared
+/// text, and the sorted classes of its children.
+type SubtreeKey = (String, Vec<(String, String)>, String, Vec<usize>);
+
+/// The class of the subtree of `root`, equal to that of another subtree
+/// numbered through the same `classes` exactly when the two are equal under
+/// `options` with children in any order. Works bottom-up with an explicit
+/// stack so deep trees can be compared.
+fn subtree_class(
+    root: &XMLNode,
+    options: &CompareOptions,
+    classes: &mut HashMap<SubtreeKey, usize>,
+) -> usize {
+    let mut stack: Vec<(Rc<XMLNode>, bool)> = vec![(Rc::new(root.clone()), false)];
+    // Classes of the finished nodes whose parent is not finished yet.
+    let mut finished: Vec<usize> = Vec::new();
+
+    while let Some((node, expanded)) = stack.pop() {
+        let children = node.cloned_children();
+        if !expanded {
+            stack.push((node, true));
+            stack.extend(children.into_iter().map(|child| (child, false)));
+            continue;
+        }
+        let mut child_classes = finished.split_off(finished.len() - children.len());
+        child_classes.sort_unstable();
+        let mut attributes: Vec<(String, String)> = node
+            .attribute_map_ref()
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        attributes.sort();
+
+        let key = (
+            node.tag.name.clone(),
+            attributes,
+            compared_text(&node.content.borrow(), options),
+            child_classes,
+        );
+        let next = classes.len();
+        finished.push(*classes.entry(key).or_insert(next));
+    }
+    finished[0]
+}
+
+/// `text` in the form `equals_ignoring` compares it in.
+fn compared_text(text: &str, options: &CompareOptions) -> String {
+    let text = match options.whitespace {
+        true => text.split_whitespace().collect::<Vec<&str>>().join(" "),
+        false => String::from(text),
+    };
+    match options.text_case {
+        true => text.to_lowercase(),
+        false => text,
+    }
+}
+
+/// Checks `name` against the `Name` production of the XML 1.0 spec.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) if is_name_start_char(first) => chars.all(is_name_char),
+        _ => false,
+    }
+}
+
+fn is_name_start_char(c: char) -> bool {
+    matches!(c,
+        ':' | 'A'..='Z' | '_' | 'a'..='z'
+        | '\u{C0}'..='\u{D6}'
+        | '\u{D8}'..='\u{F6}'
+        | '\u{F8}'..='\u{2FF}'
+        | '\u{370}'..='\u{37D}'
+        | '\u{37F}'..='\u{1FFF}'
+        | '\u{200C}'..='\u{200D}'
+        | '\u{2070}'..='\u{218F}'
+        | '\u{2C00}'..='\u{2FEF}'
+        | '\u{3001}'..='\u{D7FF}'
+        | '\u{F900}'..='\u{FDCF}'
+        | '\u{FDF0}'..='\u{FFFD}'
+        | '\u{10000}'..='\u{EFFFF}')
+}
+
+fn is_name_char(c: char) -> bool {
+    is_name_start_char(c)
+        || matches!(c,
+            '-' | '.' | '0'..='9'
+            | '\u{B7}'
+            | '\u{300}'..='\u{36F}'
+            | '\u{203F}'..='\u{2040}')
+}
+
 impl PartialEq for XMLNode {
+    /// Compares tags, text and children recursively, using an explicit stack
+    /// so arbitrarily deep trees can be compared.
     fn eq(&self, other: &Self) -> bool {
-        self.tag == other.tag && self.content == other.content && self.children == other.children
+        let mut pairs: Vec<(Rc<XMLNode>, Rc<XMLNode>)> = Vec::new();
+        if !self.shallow_eq(other, &mut pairs, true) {
+            return false;
+        }
+        while let Some((left, right)) = pairs.pop() {
+            if !left.shallow_eq(&right, &mut pairs, true) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl Drop for XMLNode {
+    /// Children this node holds the last reference to are dropped from an
+    /// explicit stack instead of recursively, so deep trees cannot overflow
+    /// the stack when released.
+    fn drop(&mut self) {
+        let mut orphans: Vec<Rc<XMLNode>> = self.children.take();
+        while let Some(child) = orphans.pop() {
+            if let Ok(child) = Rc::try_unwrap(child) {
+                orphans.append(&mut child.children.take());
+            }
+        }
     }
 }
 
@@ -117,3 +1171,1198 @@ impl Display for XMLNode {
         write!(f, "{}", self.pretty_format())
     }
 }
+
+/// Serialized as its tag, its text split into runs around the children as
+/// `text_runs` does, and its children. Parent links and spans are left out.
+#[cfg(feature = "serde")]
+impl serde::Serialize for XMLNode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let children = self.children.borrow();
+        let children: Vec<&XMLNode> = children.iter().map(|child| &**child).collect();
+        let mut node = serializer.serialize_struct("XMLNode", 3)?;
+        node.serialize_field("tag", &self.tag)?;
+        node.serialize_field("text", &self.text_runs())?;
+        node.serialize_field("children", &children)?;
+        node.end()
+    }
+}
+
+/// Builds an `XMLNode` piece by piece, started by `XMLNode::element`. Text and
+/// children keep the order they are added in, and the built node is at
+/// position 0 as it comes from no document.
+pub struct XMLNodeBuilder {
+    node: XMLNode,
+}
+
+impl XMLNodeBuilder {
+    /// Sets attribute `key`, a later call for the same key replacing it.
+    pub fn attr(self, key: &str, value: &str) -> Self {
+        self.node.set_attribute(key, value);
+        self
+    }
+    pub fn text(self, text: &str) -> Self {
+        self.node.push_content(text);
+        self
+    }
+    pub fn child(self, child: Rc<XMLNode>) -> Self {
+        self.node.append_child(child);
+        self
+    }
+    /// The finished node, the parent of every child added.
+    pub fn build(self) -> Rc<XMLNode> {
+        let node = Rc::new(self.node);
+        for child in node.children.borrow().iter() {
+            child.set_parent(&node);
+        }
+        node
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::namespace::XML_NAMESPACE;
+    use crate::parsedoc::XMLParser;
+
+    fn parse(text: &str) -> Rc<XMLNode> {
+        XMLParser::new(text).parse().unwrap()
+    }
+
+    fn child_names(node: &XMLNode) -> Vec<String> {
+        node.children
+            .borrow()
+            .iter()
+            .map(|c| c.tag.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_append_children_from_vec() {
+        let root = parse("<root><a></a></root>");
+        let new_children = vec![
+            Rc::new(XMLNode::new(XMLTag::new(
+                0,
+                String::from("b"),
+                HashMap::new(),
+            ))),
+            Rc::new(XMLNode::new(XMLTag::new(
+                0,
+                String::from("c"),
+                HashMap::new(),
+            ))),
+        ];
+
+        root.append_children(new_children);
+
+        assert_eq!(child_names(&root), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_append_children_from_filtered_iterator() {
+        let source = parse("<list><keep></keep><drop></drop><keep></keep></list>");
+        let target = parse("<target></target>");
+
+        target.append_children(
+            source
+                .children
+                .borrow()
+                .iter()
+                .filter(|c| c.tag.name == "keep")
+                .cloned(),
+        );
+
+        assert_eq!(child_names(&target), vec!["keep", "keep"]);
+    }
+
+    #[test]
+    fn test_rename_attribute() {
+        let node = parse("<div data-foo='1' id='x'></div>");
+
+        assert!(node.rename_attribute("data-foo", "foo"));
+
+        let attributes = node.tag.attributes();
+        assert_eq!(attributes.get("foo").map(String::as_str), Some("1"));
+        assert!(!attributes.contains_key("data-foo"));
+        assert_eq!(attributes.len(), 2);
+    }
+
+    #[test]
+    fn test_rename_missing_attribute() {
+        let node = parse("<div id='x'></div>");
+
+        assert!(!node.rename_attribute("missing", "foo"));
+        assert_eq!(
+            *node.attribute_map_ref(),
+            HashMap::from([(String::from("id"), String::from("x"))])
+        );
+    }
+
+    #[test]
+    fn test_rename_attribute_overwrites_existing() {
+        let node = parse("<div data-foo='new' foo='old'></div>");
+
+        assert!(node.rename_attribute("data-foo", "foo"));
+        assert_eq!(
+            *node.attribute_map_ref(),
+            HashMap::from([(String::from("foo"), String::from("new"))])
+        );
+    }
+
+    #[test]
+    fn test_set_and_remove_attribute() {
+        let node = parse("<div id='x'></div>");
+
+        assert_eq!(node.set_attribute("id", "y"), Some(String::from("x")));
+        assert_eq!(node.set_attribute("class", "c"), None);
+        assert_eq!(node.remove_attribute("id"), Some(String::from("y")));
+        assert_eq!(node.remove_attribute("id"), None);
+        assert_eq!(
+            *node.attribute_map_ref(),
+            HashMap::from([(String::from("class"), String::from("c"))])
+        );
+    }
+
+    #[test]
+    fn test_height_and_leaf_count_balanced() {
+        let root = parse("<r><a><x></x><y></y></a><b><z></z><w></w></b></r>");
+
+        assert_eq!(root.height(), 2);
+        assert_eq!(root.leaf_count(), 4);
+    }
+
+    #[test]
+    fn test_height_and_leaf_count_unbalanced() {
+        let root = parse("<r><a><b><c></c></b></a><d></d></r>");
+
+        assert_eq!(root.height(), 3);
+        assert_eq!(root.leaf_count(), 2);
+
+        let leaf = parse("<leaf>text</leaf>");
+        assert_eq!(leaf.height(), 0);
+        assert_eq!(leaf.leaf_count(), 1);
+    }
+
+    #[test]
+    fn test_text_content_in_document_order() {
+        let root = parse("<a>x<b>y<c>z</c></b>w<d></d>v</a>");
+
+        assert_eq!(root.text_content(), "xyzwv");
+    }
+
+    #[test]
+    fn test_collect_text_into_shared_buffer() {
+        let root = parse("<list><i>one<b>1</b></i><i>two</i><i></i><i>three</i></list>");
+
+        let mut buf = String::from(">");
+        for item in root.children.borrow().iter() {
+            item.collect_text_into(&mut buf);
+            buf.push('|');
+        }
+
+        assert_eq!(buf, ">one1|two||three|");
+    }
+
+    #[test]
+    fn test_diff_text() {
+        let a = parse("<p>Hello<b>world</b></p>");
+        let b = parse("<div>Hello<i>world</i></div>");
+        let c = parse("<p>Hello<b>there</b></p>");
+
+        assert_eq!(a.diff_text(&b), None);
+        assert_eq!(
+            a.diff_text(&c),
+            Some((String::from("Helloworld"), String::from("Hellothere")))
+        );
+    }
+
+    #[test]
+    fn test_strip_tags_mixed_content() {
+        let root = parse(
+            "<doc><title>Intro</title><p>Hello <b>brave</b> world</p><p><i>Bye</i></p>!</doc>",
+        );
+
+        assert_eq!(root.strip_tags(), "Intro Hello brave world Bye !");
+    }
+
+    #[test]
+    fn test_select() {
+        let root = parse(
+            "<shop><aisle id='fruit'><item class='sale new' id='apple'/><item class='sale'/><box><item class='new'/></box></aisle><item id='bread' class='basic'/><note class='sale'/></shop>",
+        );
+        let ids = |nodes: Vec<Rc<XMLNode>>| -> Vec<String> {
+            nodes
+                .iter()
+                .map(|n| {
+                    n.attr("id")
+                        .unwrap_or_else(|| n.attr_or("class", "-"))
+                        .to_string()
+                })
+                .collect()
+        };
+
+        assert_eq!(ids(root.select("item")), ["apple", "sale", "new", "bread"]);
+        assert_eq!(ids(root.select("item.sale")), ["apple", "sale"]);
+        assert_eq!(ids(root.select("item.new.sale")), ["apple"]);
+        assert_eq!(ids(root.select(".sale")), ["apple", "sale", "sale"]);
+        assert_eq!(ids(root.select("#bread")), ["bread"]);
+        assert_eq!(ids(root.select("item#apple")), ["apple"]);
+        assert_eq!(ids(root.select("aisle item")), ["apple", "sale", "new"]);
+        assert_eq!(ids(root.select("#fruit box .new")), ["new"]);
+        assert_eq!(ids(root.select("aisle aisle")), Vec::<String>::new());
+        assert!(root.select("shop item").is_empty());
+        assert!(root.select("item.").is_empty());
+        assert!(root.select("").is_empty());
+    }
+
+    #[test]
+    fn test_text_recursive() {
+        assert_eq!(
+            parse("<p>Hello <b>brave</b> world</p>").text_recursive(),
+            "Hello brave world"
+        );
+        assert_eq!(
+            parse("<p>Hello<b>brave</b>world<i></i>!</p>").text_recursive(),
+            "Hello brave world !"
+        );
+        assert_eq!(
+            parse("<doc><t>a\n</t>  <t>b</t></doc>").text_recursive(),
+            "a\n  b"
+        );
+        assert_eq!(parse("<doc><e/></doc>").text_recursive(), "");
+    }
+
+    #[test]
+    fn test_text_order_with_appended_children() {
+        let root = parse("<a>before</a>");
+        root.append_child(parse("<b>child</b>"));
+        root.push_content("after");
+        root.children.borrow_mut().push(parse("<c>pushed</c>"));
+
+        assert_eq!(root.text_content(), "beforechildafterpushed");
+    }
+
+    #[test]
+    fn test_namespaces() {
+        let root = parse(
+            "<lib xmlns='urn:default' xmlns:b='urn:books'><b:book xlink:href='#1' xmlns:xlink='urn:xlink'><title xml:lang='en'/><b:isbn/></b:book><plain xmlns=''><inner/></plain></lib>",
+        );
+        let book = root.find_first("b:book").unwrap();
+        assert_eq!(book.tag.prefix(), Some("b"));
+        assert_eq!(book.tag.local_name(), "book");
+        assert_eq!(root.tag.prefix(), None);
+        assert_eq!(root.tag.local_name(), "lib");
+
+        assert_eq!(root.namespace().as_deref(), Some("urn:default"));
+        assert_eq!(book.namespace().as_deref(), Some("urn:books"));
+        let isbn = root.find_first("b:isbn").unwrap();
+        assert_eq!(isbn.namespace().as_deref(), Some("urn:books"));
+        let title = root.find_first("title").unwrap();
+        assert_eq!(title.namespace().as_deref(), Some("urn:default"));
+        assert_eq!(
+            title.lookup_namespace(Some("xml")).as_deref(),
+            Some(XML_NAMESPACE)
+        );
+
+        // Attribute prefixes resolve the same way.
+        assert_eq!(book.attr("xlink:href").as_deref(), Some("#1"));
+        assert_eq!(
+            book.lookup_namespace(Some("xlink")).as_deref(),
+            Some("urn:xlink")
+        );
+        assert_eq!(root.lookup_namespace(Some("xlink")), None);
+
+        assert_eq!(root.find_first("plain").unwrap().namespace(), None);
+        assert_eq!(root.find_first("inner").unwrap().namespace(), None);
+        assert_eq!(root.lookup_namespace(Some("missing")), None);
+    }
+
+    #[test]
+    fn test_namespace_context_redefined_prefix() {
+        let root = parse(
+            "<p:r xmlns:p='urn:outer' xmlns='urn:default'><p:a xmlns:p='urn:inner'><p:b xmlns=''/></p:a><p:c/></p:r>",
+        );
+        let a = root.find_first("p:a").unwrap();
+        let b = root.find_first("p:b").unwrap();
+        let c = root.find_first("p:c").unwrap();
+
+        let outer = root.namespace_context();
+        assert_eq!(outer.resolve(Some("p")), Some("urn:outer"));
+        assert_eq!(outer.resolve(None), Some("urn:default"));
+        assert_eq!(outer.resolve(Some("xml")), Some(XML_NAMESPACE));
+        assert_eq!(outer.resolve(Some("q")), None);
+
+        // Redefined on `p:a`, the prefix resolves to the inner URI below it
+        // and to the outer one again on the sibling `p:c`.
+        assert_eq!(a.namespace_context().resolve(Some("p")), Some("urn:inner"));
+        let inner = b.namespace_context();
+        assert_eq!(inner.resolve(Some("p")), Some("urn:inner"));
+        assert_eq!(inner.resolve(None), None);
+        assert_eq!(c.namespace_context().resolve(Some("p")), Some("urn:outer"));
+        assert_eq!(c.namespace().as_deref(), Some("urn:outer"));
+
+        let mut bindings: Vec<_> = inner.bindings().collect();
+        bindings.sort();
+        assert_eq!(bindings, [(Some("p"), "urn:inner")]);
+    }
+
+    #[test]
+    fn test_builder() {
+        let root = XMLNode::element("a")
+            .attr("x", "1")
+            .child(XMLNode::element("b").build())
+            .text("hi")
+            .build();
+
+        assert_eq!(root.tag._pos, 0);
+        assert_eq!(root.canonicalize(), "<a x=\"1\"><b></b>hi</a>");
+        assert_eq!(
+            root.canonicalize(),
+            parse("<a x='1'><b/>hi</a>").canonicalize()
+        );
+        assert!(Rc::ptr_eq(
+            &root.children.borrow()[0].parent().unwrap(),
+            &root
+        ));
+
+        let escaped = XMLNode::element("p")
+            .attr("q", "\"<&")
+            .text("1 < 2")
+            .build();
+        assert_eq!(
+            escaped.canonicalize(),
+            "<p q=\"&quot;&lt;&amp;\">1 &lt; 2</p>"
+        );
+    }
+
+    #[test]
+    fn test_structurally_eq_ignores_positions() {
+        let compact = parse("<a x='1'><b>t</b><c/></a>");
+        let spaced = parse("<a   x='1'><b >t</b><c  /></a>");
+        let built = XMLNode::element("a")
+            .attr("x", "1")
+            .child(XMLNode::element("b").text("t").build())
+            .child(XMLNode::element("c").build())
+            .build();
+
+        assert!(compact.structurally_eq(&spaced));
+        assert!(compact.structurally_eq(&built));
+        assert!(*compact != *spaced);
+        assert!(*compact == *parse("<a x='1'><b>t</b><c/></a>"));
+
+        assert!(!compact.structurally_eq(&parse("<a x='2'><b>t</b><c/></a>")));
+        assert!(!compact.structurally_eq(&parse("<a x='1'><b>u</b><c/></a>")));
+        assert!(!compact.structurally_eq(&parse("<a x='1'><b>t</b><d/></a>")));
+        assert!(!compact.structurally_eq(&parse("<a x='1'><b>t</b></a>")));
+
+        // Same text and children, but the text sits on the other side of `b`.
+        let before = parse("<a>x<b/></a>");
+        let after = parse("<a><b/>x</a>");
+        assert!(!before.structurally_eq(&after));
+        assert!(*before != *after);
+    }
+
+    #[test]
+    fn test_child_by_name() {
+        let root = parse("<html><head><meta/><title>One</title><title>Two</title></head><body><title>Deep</title></body></html>");
+
+        let head = root.child("head").unwrap();
+        assert_eq!(head.child("title").unwrap().text_content(), "One");
+        assert!(head.child("meta").is_some());
+        assert!(root.child("title").is_none());
+        assert!(root.child("missing").is_none());
+        assert!(head.child("head").is_none());
+    }
+
+    #[test]
+    fn test_insert_and_remove_child() {
+        let root = parse("<list>a<x/>b</list>");
+        let names = |node: &XMLNode| -> Vec<String> {
+            node.child_nodes().map(|c| c.tag.name.clone()).collect()
+        };
+
+        root.insert_child(0, parse("<first/>"));
+        root.insert_child(2, parse("<y/>"));
+        root.insert_child(99, parse("<last/>"));
+        assert_eq!(names(&root), ["first", "x", "y", "last"]);
+        for child in root.child_nodes() {
+            assert!(Rc::ptr_eq(&child.parent().unwrap(), &root));
+        }
+        assert_eq!(root.child_count(), 4);
+        assert_eq!(
+            root.canonicalize(),
+            "<list>a<first></first><x></x>b<y></y><last></last></list>"
+        );
+
+        let x = root.find_first("x").unwrap();
+        assert!(x.parent().is_some());
+        let removed = root.remove_child(1).unwrap();
+        assert!(Rc::ptr_eq(&removed, &x));
+        assert!(x.parent().is_none());
+        assert!(root.remove_child(3).is_none());
+        assert_eq!(names(&root), ["first", "y", "last"]);
+        assert_eq!(
+            root.canonicalize(),
+            "<list>a<first></first>b<y></y><last></last></list>"
+        );
+
+        while root.remove_child(0).is_some() {}
+        assert!(!root.has_children());
+        assert_eq!(root.text_content(), "ab");
+    }
+
+    #[test]
+    fn test_find_by_attr() {
+        let root = parse(
+            "<list kind='a'><item kind='a' id='1'/><item kind='b' id='2'/><group><item kind='a' id='3'/><note kind='a'/></group><item kind='ab' id='4'/></list>",
+        );
+        let ids = |nodes: Vec<Rc<XMLNode>>| -> Vec<String> {
+            nodes
+                .iter()
+                .map(|n| n.attr_or("id", "-").to_string())
+                .collect()
+        };
+
+        assert_eq!(
+            ids(root.find_by_attr(Some("item"), "kind", "a")),
+            ["1", "3"]
+        );
+        assert_eq!(ids(root.find_by_attr(Some("item"), "kind", "b")), ["2"]);
+        assert_eq!(ids(root.find_by_attr(None, "kind", "a")), ["1", "3", "-"]);
+        assert!(root.find_by_attr(Some("item"), "kind", "A").is_empty());
+        assert!(root.find_by_attr(Some("note"), "id", "1").is_empty());
+    }
+
+    #[test]
+    fn test_get_text_and_attr_parse() {
+        let node = parse("<person age='55' height=' 1.8' name='x'>\n  Jane Doe  \n</person>");
+
+        assert_eq!(&*node.get_text(), "Jane Doe");
+        assert_eq!(node.attr_parse::<u32>("age"), Some(Ok(55)));
+        assert_eq!(
+            node.attr_parse::<f64>("height").map(|r| r.is_err()),
+            Some(true)
+        );
+        assert_eq!(node.attr_parse::<u32>("missing"), None);
+
+        let malformed = parse("<person age='abc'/>");
+        let err = malformed.attr_parse::<u32>("age").unwrap().unwrap_err();
+        assert_eq!(err, "abc".parse::<u32>().unwrap_err());
+        assert_eq!(&*malformed.get_text(), "");
+    }
+
+    #[test]
+    fn test_ensure_attribute() {
+        let node = parse("<input type='text'></input>");
+
+        assert!(!node.ensure_attribute("type", "hidden"));
+        assert!(node.ensure_attribute("name", "q"));
+        assert_eq!(
+            *node.attribute_map_ref(),
+            HashMap::from([
+                (String::from("type"), String::from("text")),
+                (String::from("name"), String::from("q")),
+            ])
+        );
+    }
+
+    fn build_from_events(events: &[Event]) -> Rc<XMLNode> {
+        let mut stack: Vec<Rc<XMLNode>> = Vec::new();
+        let mut root: Option<Rc<XMLNode>> = None;
+        for event in events {
+            match event {
+                Event::StartElement { name, attributes } => {
+                    let node = Rc::new(XMLNode::new(XMLTag::new(
+                        0,
+                        name.clone(),
+                        attributes.clone(),
+                    )));
+                    match stack.last() {
+                        Some(parent) => parent.append_child(Rc::clone(&node)),
+                        None => root = Some(Rc::clone(&node)),
+                    }
+                    stack.push(node);
+                }
+                Event::EndElement { .. } => {
+                    stack.pop();
+                }
+                Event::Text(text) => stack.last().unwrap().push_content(text),
+                Event::Eof => {}
+            }
+        }
+        root.unwrap()
+    }
+
+    #[test]
+    fn test_to_events_mixed_content() {
+        let root = parse("<p id='1'>Hello<b>brave</b>new<i></i>world</p>");
+
+        let start = |name: &str, attributes: &[(&str, &str)]| Event::StartElement {
+            name: String::from(name),
+            attributes: attributes
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        };
+        let end = |name: &str| Event::EndElement {
+            name: String::from(name),
+        };
+        let text = |t: &str| Event::Text(String::from(t));
+
+        assert_eq!(
+            root.to_events(),
+            vec![
+                start("p", &[("id", "1")]),
+                text("Hello"),
+                start("b", &[]),
+                text("brave"),
+                end("b"),
+                text("new"),
+                start("i", &[]),
+                end("i"),
+                text("world"),
+                end("p"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_events_round_trip() {
+        let root = parse("<r a='x'>lead<s>1<t>2</t>3</s>mid<u></u>tail</r>");
+
+        let events = root.to_events();
+        let rebuilt = build_from_events(&events);
+
+        assert_eq!(rebuilt.to_events(), events);
+    }
+
+    #[test]
+    fn test_first_text() {
+        let label = parse("<label>Name<b>bold</b>tail</label>");
+        assert_eq!(label.first_text().as_deref(), Some("Name"));
+
+        let element_first = parse("<label><b>bold</b>tail</label>");
+        assert!(element_first.first_text().is_none());
+
+        assert_eq!(parse("<p>only</p>").first_text().as_deref(), Some("only"));
+        assert!(parse("<p></p>").first_text().is_none());
+    }
+
+    #[test]
+    fn test_attributes_with_prefix() {
+        let node =
+            parse("<div id='d' data-role='menu' aria-label='Menu' data-open='no' metadata-x='1'/>");
+
+        assert_eq!(
+            node.attributes_with_prefix("data-"),
+            [
+                (String::from("data-open"), String::from("no")),
+                (String::from("data-role"), String::from("menu")),
+            ]
+        );
+        assert!(node.attributes_with_prefix("xml").is_empty());
+    }
+
+    #[test]
+    fn test_map_attributes() {
+        let node = parse("<img src='a.png' alt='A' class='thumb' data-x='1' data-y='2'/>");
+
+        node.map_attributes(|name, value| match name {
+            "class" => Some((String::from("className"), String::from(value))),
+            "src" => Some((String::from(name), format!("/static/{}", value))),
+            _ if name.starts_with("data-") => None,
+            _ => Some((String::from(name), String::from(value))),
+        });
+
+        assert_eq!(
+            *node.attribute_map_ref(),
+            HashMap::from([
+                (String::from("src"), String::from("/static/a.png")),
+                (String::from("alt"), String::from("A")),
+                (String::from("className"), String::from("thumb")),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_attr_helpers() {
+        let person = parse("<person name='John'/>");
+
+        assert_eq!(person.attr("name").as_deref(), Some("John"));
+        assert!(person.attr("missing").is_none());
+        assert!(person.has_attr("name"));
+        assert!(!person.has_attr("missing"));
+        assert_eq!(&*person.attr_or("name", "anonymous"), "John");
+        assert_eq!(&*person.attr_or("missing", "anonymous"), "anonymous");
+    }
+
+    #[test]
+    fn test_attribute_map_ref() {
+        let node = parse("<img src='a.png' alt='A'></img>");
+
+        {
+            let attributes = node.attribute_map_ref();
+            let mut names: Vec<&str> = attributes.keys().map(String::as_str).collect();
+            names.sort();
+            assert_eq!(names, vec!["alt", "src"]);
+            assert_eq!(attributes["src"], "a.png");
+        }
+
+        node.set_attribute("src", "b.png");
+        assert_eq!(node.attribute_map_ref()["src"], "b.png");
+    }
+
+    #[test]
+    fn test_find_first_and_find_all() {
+        let root = parse("<a><b id='1'/><c><b id='2'/></c></a>");
+
+        let all = root.find_all("b");
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[1].attr("id").as_deref(), Some("2"));
+        assert!(Rc::ptr_eq(&root.find_first("b").unwrap(), &all[0]));
+
+        // The node searched from is a candidate too.
+        assert!(Rc::ptr_eq(&root.find_first("a").unwrap(), &root));
+        let c = root.find_first("c").unwrap();
+        assert!(Rc::ptr_eq(&c.find_all("c")[0], &c));
+        assert_eq!(c.find_all("b").len(), 1);
+        assert_eq!(root.find_all("a").len(), 1);
+        assert_eq!(parse("<b><b/></b>").find_all("b").len(), 2);
+        assert!(root.find_first("d").is_none());
+    }
+
+    #[test]
+    fn test_find_all_with_path() {
+        let root = parse(
+            "<root><section><error/></section><section><p/><error id='a'/><error id='b'/></section><error/></root>",
+        );
+
+        let paths: Vec<String> = root
+            .find_all_with_path("error")
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "/root/section[1]/error",
+                "/root/section[2]/error[1]",
+                "/root/section[2]/error[2]",
+                "/root/error",
+            ]
+        );
+
+        let found = root.find_all_with_path("error");
+        assert_eq!(found[2].1.attr("id").as_deref(), Some("b"));
+        assert!(root.find_all_with_path("root").is_empty());
+    }
+
+    #[test]
+    fn test_find_n() {
+        let root = parse("<a><b id='1'><b id='2'></b></b><c><b id='3'></b></c><b id='4'></b></a>");
+
+        let ids = |nodes: Vec<Rc<XMLNode>>| -> Vec<String> {
+            nodes
+                .iter()
+                .map(|n| n.attribute_map_ref()["id"].clone())
+                .collect()
+        };
+
+        assert_eq!(ids(root.find_n("b", 2)), vec!["1", "2"]);
+        assert_eq!(ids(root.find_n("b", 10)), vec!["1", "2", "3", "4"]);
+        assert!(root.find_n("b", 0).is_empty());
+        assert!(root.find_n("a", 5).is_empty());
+    }
+
+    #[test]
+    fn test_prune_if_removes_hidden_subtrees() {
+        let root = parse(
+            "<r>a<x hidden='1'><y></y></x>b<y><x hidden='1'></x>c<z><w hidden='yes'></w></z></y><v></v></r>",
+        );
+
+        root.prune_if(|node| node.attribute_map_ref().contains_key("hidden"));
+
+        assert_eq!(
+            root.to_events(),
+            parse("<r>ab<y>c<z></z></y><v></v></r>").to_events()
+        );
+        assert!(root
+            .iter_pairs()
+            .all(|(_, node)| !node.attribute_map_ref().contains_key("hidden")));
+    }
+
+    #[test]
+    fn test_prune_if_detaches_and_lets_the_predicate_read_the_tree() {
+        let root = parse("<r><a/><b><c/></b></r>");
+        let a = root.find_first("a").unwrap();
+
+        // The predicate looks at the siblings of the node it is given.
+        root.prune_if(|node| {
+            node.parent()
+                .is_some_and(|parent| parent.child_count() > 1 && node.tag.name == "a")
+        });
+
+        assert!(a.parent().is_none());
+        assert_eq!(child_names(&root), ["b"]);
+        assert!(root.find_first("c").unwrap().parent().is_some());
+    }
+
+    #[test]
+    fn test_equals_ignoring_toggles() {
+        let left = parse("<list k='v'><item>Apple</item><item>Pear</item></list>");
+        let right = parse("<list k='v'><item>pear</item><item>apple</item></list>");
+        left.children.borrow()[0].push_content("  ");
+
+        let options = |whitespace, text_case, element_order| CompareOptions {
+            whitespace,
+            text_case,
+            element_order,
+        };
+
+        assert!(!left.equals_ignoring(&right, &options(false, false, false)));
+        assert!(!left.equals_ignoring(&right, &options(true, true, false)));
+        assert!(!left.equals_ignoring(&right, &options(true, false, true)));
+        assert!(!left.equals_ignoring(&right, &options(false, true, true)));
+        assert!(left.equals_ignoring(&right, &options(true, true, true)));
+
+        let reordered = parse("<list k='v'><item>Pear</item><item>Apple</item></list>");
+        assert!(reordered.equals_ignoring(&left, &options(true, false, true)));
+        assert!(!reordered.equals_ignoring(&left, &options(true, false, false)));
+
+        let duplicated = parse("<list k='v'><item>Pear</item><item>Pear</item></list>");
+        assert!(!duplicated.equals_ignoring(&reordered, &options(true, true, true)));
+    }
+
+    #[test]
+    fn test_descendants_pre_order() {
+        let root = parse("<a><b><c/><d><e/></d></b><f><g/></f></a>");
+
+        let names: Vec<String> = root.descendants().map(|n| n.tag.name.clone()).collect();
+        assert_eq!(names, ["b", "c", "d", "e", "f", "g"]);
+
+        let mut lazy = root.descendants();
+        assert_eq!(lazy.next().unwrap().tag.name, "b");
+        // Children added before their parent is reached are still visited.
+        root.children.borrow()[1].append_child(Rc::new(XMLNode::new(XMLTag::new(
+            0,
+            String::from("h"),
+            HashMap::new(),
+        ))));
+        assert_eq!(lazy.last().unwrap().tag.name, "h");
+    }
+
+    #[test]
+    fn test_walk_pre_order_with_depth() {
+        let root = parse("<a><b><c/><d><e/></d></b>text<f><g/></f></a>");
+
+        let mut visited: Vec<(String, usize)> = Vec::new();
+        root.walk(|node, depth| visited.push((node.tag.name.clone(), depth)));
+        let expected = [
+            ("a", 0),
+            ("b", 1),
+            ("c", 2),
+            ("d", 2),
+            ("e", 3),
+            ("f", 1),
+            ("g", 2),
+        ];
+        let expected: Vec<(String, usize)> = expected
+            .iter()
+            .map(|&(name, depth)| (String::from(name), depth))
+            .collect();
+        assert_eq!(visited, expected);
+
+        let mut count = 0;
+        root.children.borrow()[0].walk(|_, _| count += 1);
+        assert_eq!(count, 4);
+    }
+
+    #[test]
+    fn test_descendant_count() {
+        let root = parse("<a><b><c></c><d><e></e></d></b><f/>text</a>");
+
+        assert_eq!(root.descendant_count(), 5);
+        assert_eq!(root.children.borrow()[0].descendant_count(), 3);
+        assert_eq!(root.children.borrow()[1].descendant_count(), 0);
+    }
+
+    #[test]
+    fn test_iter_pairs_depths() {
+        let root = parse("<a><b><c></c><d><e></e></d></b><f></f></a>");
+
+        let pairs: Vec<(usize, String)> = root
+            .iter_pairs()
+            .map(|(depth, node)| (depth, node.tag.name.clone()))
+            .collect();
+        let expected = [(0, "a"), (1, "b"), (2, "c"), (2, "d"), (3, "e"), (1, "f")];
+        assert_eq!(
+            pairs,
+            expected.map(|(depth, name)| (depth, String::from(name)))
+        );
+
+        let b = Rc::clone(&root.children.borrow()[0]);
+        assert_eq!(b.iter_pairs().map(|(depth, _)| depth).max(), Some(2));
+    }
+
+    #[test]
+    fn test_content_len_and_attribute_count() {
+        let root = parse("<r><a>abc<b x='1' y='2'>z</b></a><c k='v'></c><d>日本</d></r>");
+        let a = Rc::clone(&root.children.borrow()[0]);
+        let b = Rc::clone(&a.children.borrow()[0]);
+
+        assert_eq!((root.content_len(), root.attribute_count()), (0, 0));
+        assert_eq!((a.content_len(), a.attribute_count()), (3, 0));
+        assert_eq!((b.content_len(), b.attribute_count()), (1, 2));
+
+        let c = Rc::clone(&root.children.borrow()[1]);
+        assert_eq!((c.content_len(), c.attribute_count()), (0, 1));
+        assert_eq!(root.children.borrow()[2].content_len(), 6);
+
+        c.remove_attribute("k");
+        assert_eq!(c.attribute_count(), 0);
+    }
+
+    #[test]
+    fn test_is_whitespace_only() {
+        let empty = parse("<a><b>text</b></a>");
+        let whitespace = parse("<a></a>");
+        whitespace.push_content(" \n\t ");
+        let text = parse("<a> x </a>");
+
+        assert!(empty.is_whitespace_only());
+        assert!(whitespace.is_whitespace_only());
+        assert!(!text.is_whitespace_only());
+    }
+
+    #[test]
+    fn test_set_attribute_checked() {
+        let node = parse("<div></div>");
+
+        for name in ["a b", "a<b", "1abc", "", "-x"] {
+            match node.set_attribute_checked(name, "v") {
+                Ok(_) => panic!("Expected InvalidName for {:?}, got Ok", name),
+                Err(e) => match e {
+                    ValidationError::InvalidName(rejected) => assert_eq!(rejected, name),
+                    _ => panic!("Expected InvalidName, got Err({:?})", e),
+                },
+            }
+        }
+        assert!(node.attribute_map_ref().is_empty());
+
+        for name in ["id", "data-foo", "_x.1", "xml:lang", "été"] {
+            node.set_attribute_checked(name, "v").unwrap();
+        }
+        assert_eq!(node.attribute_map_ref().len(), 5);
+    }
+
+    #[test]
+    fn test_cloned_children_snapshot() {
+        let root = parse("<root><a></a><b></b></root>");
+
+        let mut visited: Vec<String> = Vec::new();
+        for child in root.cloned_children() {
+            visited.push(child.tag.name.clone());
+            root.append_child(parse("<added></added>"));
+        }
+
+        assert_eq!(visited, vec!["a", "b"]);
+        assert_eq!(child_names(&root), vec!["a", "b", "added", "added"]);
+    }
+
+    #[test]
+    fn test_child_nodes() {
+        let root = parse("<root><a/><b/><c/></root>");
+
+        let names: Vec<String> = root.child_nodes().map(|c| c.tag.name.clone()).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+        assert_eq!(root.child_count(), 3);
+        assert!(root.has_children());
+
+        let leaf = root.child_nodes().next().unwrap();
+        assert_eq!(leaf.child_count(), 0);
+        assert!(!leaf.has_children());
+        assert_eq!(leaf.child_nodes().count(), 0);
+    }
+
+    #[test]
+    fn test_child_index() {
+        let root = parse("<root><a></a><b></b><a></a></root>");
+        let children = root.cloned_children();
+
+        assert_eq!(root.child_index(&children[0]), Some(0));
+        assert_eq!(root.child_index(&children[2]), Some(2));
+
+        let lookalike = parse("<a></a>");
+        assert_eq!(root.child_index(&lookalike), None);
+        assert_eq!(children[1].child_index(&children[0]), None);
+    }
+
+    #[test]
+    fn test_splice_children() {
+        let leaf = |name: &str| {
+            Rc::new(XMLNode::new(XMLTag::new(
+                0,
+                name.to_string(),
+                HashMap::new(),
+            )))
+        };
+
+        let root = parse("<t>head<a></a>x<b></b>y<c></c>z<d></d>tail</t>");
+        let removed = root.splice_children(1..3, vec![leaf("p"), leaf("q"), leaf("r")]);
+        assert_eq!(
+            removed
+                .iter()
+                .map(|c| c.tag.name.as_str())
+                .collect::<Vec<_>>(),
+            ["b", "c"]
+        );
+        assert!(removed.iter().all(|c| c.parent().is_none()));
+        assert_eq!(child_names(&root), ["a", "p", "q", "r", "d"]);
+        let q = root.child("q").unwrap();
+        assert!(Rc::ptr_eq(&q.parent().unwrap(), &root));
+        assert_eq!(
+            root.to_events(),
+            parse("<t>head<a></a>x<p></p><q></q><r></r>yz<d></d>tail</t>").to_events()
+        );
+
+        let removed = root.splice_children(0..4, vec![leaf("s")]);
+        assert_eq!(removed.len(), 4);
+        assert_eq!(
+            root.to_events(),
+            parse("<t>head<s></s>xyz<d></d>tail</t>").to_events()
+        );
+
+        let removed = root.splice_children(1..10, Vec::new());
+        assert_eq!(removed.len(), 1);
+        root.splice_children(7..9, vec![leaf("e")]);
+        assert_eq!(
+            root.to_events(),
+            parse("<t>head<s></s>xyztail<e></e></t>").to_events()
+        );
+    }
+
+    #[test]
+    fn test_ancestors_and_parent_named() {
+        let root = parse("<table><tr><td><b>x</b></td></tr><caption/></table>");
+        let b = root.find_first("b").unwrap();
+
+        let names: Vec<String> = b.ancestors().map(|n| n.tag.name.clone()).collect();
+        assert_eq!(names, ["td", "tr", "table"]);
+        assert!(Rc::ptr_eq(&b.parent_named("table").unwrap(), &root));
+        assert_eq!(b.parent_named("td").unwrap().tag.name, "td");
+
+        assert!(b.parent_named("b").is_none());
+        assert!(b.parent_named("caption").is_none());
+        assert!(root.parent_named("table").is_none());
+        assert_eq!(root.ancestors().count(), 0);
+    }
+
+    #[test]
+    fn test_children_edited_directly() {
+        let leaf = |name: &str| XMLNode::element(name).build();
+        let root = parse("<t>x<a/>y<b/>z</t>");
+
+        // Pushed directly, a child follows all of the text.
+        root.children.borrow_mut().push(leaf("c"));
+        assert_eq!(root.text_runs(), ["x", "y", "z", ""]);
+        root.append_child(leaf("d"));
+        root.push_content("w");
+        assert_eq!(root.text_runs(), ["x", "y", "z", "", "w"]);
+        assert_eq!(
+            root.canonicalize(),
+            "<t>x<a></a>y<b></b>z<c></c><d></d>w</t>"
+        );
+
+        // Offsets left over from children removed directly are ignored.
+        root.children.borrow_mut().truncate(2);
+        assert_eq!(root.text_runs(), ["x", "y", "zw"]);
+        root.insert_child(2, leaf("e"));
+        assert_eq!(root.canonicalize(), "<t>x<a></a>y<b></b>zw<e></e></t>");
+
+        root.children.borrow_mut().clear();
+        root.apply_text_mode(TextMode::Trim);
+        assert_eq!(root.text_runs(), ["xyzw"]);
+        assert_eq!(root.canonicalize(), "<t>xyzw</t>");
+    }
+
+    /// A chain of `levels` nested `n` elements below the returned root,
+    /// along with the innermost one.
+    fn deep_chain(levels: usize) -> (Rc<XMLNode>, Rc<XMLNode>) {
+        let root = XMLNode::element("n").build();
+        let mut deepest = Rc::clone(&root);
+        for _ in 0..levels {
+            let child = XMLNode::element("n").build();
+            deepest.append_child(Rc::clone(&child));
+            deepest = child;
+        }
+        (root, deepest)
+    }
+
+    #[test]
+    fn test_deep_tree_height_and_leaf_count() {
+        let (root, deepest) = deep_chain(100_000);
+        deepest.append_child(XMLNode::element("leaf").build());
+
+        assert_eq!(root.height(), 100_001);
+        assert_eq!(root.leaf_count(), 1);
+    }
+
+    #[test]
+    fn test_equals_ignoring_deep_tree() {
+        let (left, left_deepest) = deep_chain(100_000);
+        let (right, right_deepest) = deep_chain(100_000);
+        left_deepest.push_content("Bottom ");
+        right_deepest.push_content("bottom");
+
+        let options = CompareOptions {
+            whitespace: true,
+            text_case: true,
+            element_order: false,
+        };
+        assert!(left.equals_ignoring(&right, &options));
+        assert!(!left.equals_ignoring(&right, &CompareOptions::default()));
+
+        let unordered = CompareOptions {
+            element_order: true,
+            ..options
+        };
+        assert!(left.equals_ignoring(&right, &unordered));
+        right_deepest.push_content("!");
+        assert!(!left.equals_ignoring(&right, &unordered));
+    }
+
+    #[test]
+    fn test_walk_deep_tree() {
+        let (root, _) = deep_chain(100_000);
+
+        let (mut count, mut deepest) = (0, 0);
+        root.walk(|_, depth| {
+            count += 1;
+            deepest = deepest.max(depth);
+        });
+        assert_eq!((count, deepest), (100_001, 100_000));
+    }
+
+    #[test]
+    fn test_for_each_attribute_deep_tree() {
+        let (root, deepest) = deep_chain(100_000);
+        root.set_attribute("top", "1");
+        deepest.set_attribute("bottom", "2");
+
+        let mut names: Vec<String> = Vec::new();
+        root.for_each_attribute(|_, name, _| names.push(String::from(name)));
+        assert_eq!(names, ["top", "bottom"]);
+    }
+
+    #[test]
+    fn test_deep_tree_eq_and_deep_clone() {
+        let leaf = |name: &str| XMLNode::new(XMLTag::new(0, name.to_string(), HashMap::new()));
+
+        let root = leaf("n");
+        let mut deepest = Rc::new(leaf("n"));
+        root.append_child(Rc::clone(&deepest));
+        for _ in 0..100_000 {
+            let child = Rc::new(leaf("n"));
+            deepest.append_child(Rc::clone(&child));
+            deepest = child;
+        }
+        deepest.push_content("bottom");
+
+        let copy = root.deep_clone();
+        assert!(root == *copy);
+
+        deepest.push_content("!");
+        assert!(root != *copy);
+
+        let shallow = root.clone();
+        assert!(Rc::ptr_eq(
+            &shallow.children.borrow()[0],
+            &root.children.borrow()[0]
+        ));
+        assert!(!Rc::ptr_eq(
+            &copy.children.borrow()[0],
+            &root.children.borrow()[0]
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize() {
+        let root = parse("<a k='v'>x<b/>y</a>");
+        assert_eq!(
+            serde_json::to_value(&*root).unwrap(),
+            serde_json::json!({
+                "tag": {"position": 0, "name": "a", "attributes": {"k": "v"}},
+                "text": ["x", "y"],
+                "children": [{
+                    "tag": {"position": 10, "name": "b", "attributes": {}},
+                    "text": [""],
+                    "children": [],
+                }],
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(crate::TagKind::SelfClosing).unwrap(),
+            "SelfClosing"
+        );
+    }
+
+    #[test]
+    fn test_deep_clone_links_parents() {
+        let root = parse("<r xmlns:p='urn:x'><p:a><p:b/></p:a></r>");
+        let copy = root.deep_clone();
+        drop(root);
+
+        let a = Rc::clone(&copy.children.borrow()[0]);
+        let b = Rc::clone(&a.children.borrow()[0]);
+        assert!(Rc::ptr_eq(&b.parent().unwrap(), &a));
+        assert!(Rc::ptr_eq(&a.parent().unwrap(), &copy));
+        assert!(copy.parent().is_none());
+        assert_eq!(b.namespace().as_deref(), Some("urn:x"));
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let a = parse(
+            "<doc b='2' a=\"1\"><item x='&lt;&quot;'>one &amp; two</item><empty></empty></doc>",
+        );
+        let b = parse("<doc a='1'  b=\"2\">\n  <item x='&lt;\"'>one &amp; two</item>\n  <empty></empty>\n</doc>");
+        a.children.borrow()[0].push_content("  ");
+        b.children.borrow()[0].push_content("\n");
+
+        assert_eq!(a.canonicalize(), b.canonicalize());
+        assert_eq!(
+            a.canonicalize(),
+            "<doc a=\"1\" b=\"2\"><item x=\"&lt;&quot;\">one &amp; two</item><empty></empty></doc>"
+        );
+
+        let spaced = XMLNode::new(XMLTag::new(0, String::from("p"), HashMap::new()));
+        spaced.push_content("  a \t b\n ");
+        assert_eq!(spaced.canonicalize(), "<p>a b</p>");
+    }
+
+    #[test]
+    fn test_for_each_attribute_visits_every_attribute_once() {
+        let root =
+            parse("<root id='r'><a href='x' class='c'></a><b><a href='y'></a><c></c></b></root>");
+
+        let mut visited: Vec<(String, String, String)> = Vec::new();
+        root.for_each_attribute(|node, name, value| {
+            visited.push((node.tag.name.clone(), name.to_owned(), value.to_owned()));
+        });
+        visited.sort();
+
+        let expected: Vec<(String, String, String)> = [
+            ("a", "class", "c"),
+            ("a", "href", "x"),
+            ("a", "href", "y"),
+            ("root", "id", "r"),
+        ]
+        .iter()
+        .map(|(n, k, v)| (n.to_string(), k.to_string(), v.to_string()))
+        .collect();
+
+        assert_eq!(visited, expected);
+    }
+}
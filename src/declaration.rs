@@ -0,0 +1,153 @@
+/// The pseudo-attributes of an `<?xml ... ?>` declaration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XmlDeclaration {
+    pub version: String,
+    pub encoding: Option<String>,
+    /// `standalone='yes'` or `'no'`, `None` when absent or anything else.
+    pub standalone: Option<bool>,
+}
+
+impl XmlDeclaration {
+    /// Reads the declaration from the text between `<?xml` and `?>`. `None`
+    /// without a `version`, which every declaration must have.
+    pub fn parse(body: &str) -> Option<Self> {
+        let standalone = match pseudo_attribute(body, "standalone") {
+            Some("yes") => Some(true),
+            Some("no") => Some(false),
+            _ => None,
+        };
+        Some(Self {
+            version: String::from(pseudo_attribute(body, "version")?),
+            encoding: pseudo_attribute(body, "encoding").map(String::from),
+            standalone,
+        })
+    }
+}
+
+/// Byte order marks and the encodings they announce, longest first so UTF-32
+/// is not mistaken for UTF-16.
+const BYTE_ORDER_MARKS: [(&[u8], &str); 5] = [
+    (&[0x00, 0x00, 0xFE, 0xFF], "UTF-32BE"),
+    (&[0xFF, 0xFE, 0x00, 0x00], "UTF-32LE"),
+    (&[0xEF, 0xBB, 0xBF], "UTF-8"),
+    (&[0xFE, 0xFF], "UTF-16BE"),
+    (&[0xFF, 0xFE], "UTF-16LE"),
+];
+
+/// The encoding announced by a byte order mark or, failing that, by the
+/// `encoding` of an XML declaration at the very start of `bytes`. The
+/// declaration is read as ASCII, as every encoding it can name must allow.
+pub fn sniff_encoding(bytes: &[u8]) -> Option<String> {
+    for (mark, encoding) in BYTE_ORDER_MARKS {
+        if bytes.starts_with(mark) {
+            return Some(String::from(encoding));
+        }
+    }
+
+    let end = bytes.windows(2).position(|w| w == b"?>")?;
+    let declaration = std::str::from_utf8(&bytes[..end]).ok()?;
+    let rest = declaration.strip_prefix("<?xml")?;
+    if !rest.starts_with(|c: char| c.is_ascii_whitespace()) {
+        return None;
+    }
+    pseudo_attribute(rest, "encoding").map(String::from)
+}
+
+/// The value of pseudo-attribute `name` in the body of a declaration, like
+/// `encoding` in ` version="1.0" encoding='UTF-8'`.
+pub(crate) fn pseudo_attribute<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = text;
+    loop {
+        rest = rest.trim_start();
+        let (key, after_key) = rest.split_at(rest.find(|c: char| c == '=' || c.is_whitespace())?);
+        let after_equals = after_key.trim_start().strip_prefix('=')?.trim_start();
+
+        let quote = after_equals
+            .chars()
+            .next()
+            .filter(|&c| c == '"' || c == '\'')?;
+        let value_end = after_equals[1..].find(quote)?;
+        let value = &after_equals[1..1 + value_end];
+
+        if key == name {
+            return Some(value);
+        }
+        rest = &after_equals[value_end + 2..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff_encoding_from_byte_order_mark() {
+        assert_eq!(
+            sniff_encoding(b"\xEF\xBB\xBF<a></a>").as_deref(),
+            Some("UTF-8")
+        );
+        assert_eq!(
+            sniff_encoding(b"\xFF\xFE<\x00a\x00").as_deref(),
+            Some("UTF-16LE")
+        );
+        assert_eq!(
+            sniff_encoding(b"\xFE\xFF\x00<\x00a").as_deref(),
+            Some("UTF-16BE")
+        );
+        assert_eq!(
+            sniff_encoding(b"\xFF\xFE\x00\x00<\x00\x00\x00").as_deref(),
+            Some("UTF-32LE")
+        );
+        // The mark wins over whatever the declaration claims.
+        assert_eq!(
+            sniff_encoding(b"\xEF\xBB\xBF<?xml version='1.0' encoding='latin1'?><a/>").as_deref(),
+            Some("UTF-8")
+        );
+    }
+
+    #[test]
+    fn test_sniff_encoding_from_declaration() {
+        assert_eq!(
+            sniff_encoding(b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><a/>").as_deref(),
+            Some("ISO-8859-1")
+        );
+        assert_eq!(
+            sniff_encoding(b"<?xml version = '1.0'\n  encoding = 'Shift_JIS' standalone='yes' ?>")
+                .as_deref(),
+            Some("Shift_JIS")
+        );
+    }
+
+    #[test]
+    fn test_parse_declaration() {
+        assert_eq!(
+            XmlDeclaration::parse(" version=\"1.0\" encoding=\"UTF-8\" standalone='no'"),
+            Some(XmlDeclaration {
+                version: String::from("1.0"),
+                encoding: Some(String::from("UTF-8")),
+                standalone: Some(false),
+            })
+        );
+        assert_eq!(
+            XmlDeclaration::parse(" version='1.1'"),
+            Some(XmlDeclaration {
+                version: String::from("1.1"),
+                encoding: None,
+                standalone: None,
+            })
+        );
+        assert_eq!(XmlDeclaration::parse(" encoding='UTF-8'"), None);
+    }
+
+    #[test]
+    fn test_sniff_encoding_without_declaration() {
+        assert_eq!(sniff_encoding(b"<a></a>"), None);
+        assert_eq!(sniff_encoding(b"<?xml version='1.0'?><a/>"), None);
+        assert_eq!(sniff_encoding(b"<?xml-stylesheet encoding='x'?><a/>"), None);
+        assert_eq!(
+            sniff_encoding(b"  <?xml version='1.0' encoding='x'?>"),
+            None
+        );
+        assert_eq!(sniff_encoding(b""), None);
+    }
+}
@@ -0,0 +1,318 @@
+use crate::error::ParseError;
+use crate::options::EntityPolicy;
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Replaces the predefined entities (`&lt;`, `&gt;`, `&amp;`, `&quot;`,
+/// `&apos;`) and numeric character references (`&#38;`, `&#x26;`) in `text`.
+/// Any other `&` is kept as written under `EntityPolicy::Lenient` and is an
+/// error under `EntityPolicy::Strict`. `position` is the document offset of
+/// `text`, used for errors. Borrows `text` unchanged when there is nothing to
+/// replace.
+pub fn decode(
+    text: &str,
+    position: usize,
+    policy: EntityPolicy,
+) -> Result<Cow<'_, str>, ParseError> {
+    decode_with(text, position, policy, &HashMap::new())
+}
+
+/// Like `decode`, also replacing `&name;` with its value in `defined`, the
+/// entities the document declared itself, see `internal_entities`.
+pub fn decode_with<'t>(
+    text: &'t str,
+    position: usize,
+    policy: EntityPolicy,
+    defined: &HashMap<String, String>,
+) -> Result<Cow<'t, str>, ParseError> {
+    if !text.contains('&') {
+        return Ok(Cow::Borrowed(text));
+    }
+
+    let mut decoded = String::with_capacity(text.len());
+    let mut copied = 0;
+    let mut search = 0;
+
+    while let Some(found) = text[search..].find('&') {
+        let amp = search + found;
+        search = amp + 1;
+
+        let mut utf8 = [0; 4];
+        let resolved = match text[amp + 1..].find(';') {
+            Some(semi) => {
+                let name = &text[amp + 1..amp + 1 + semi];
+                match resolve(name, position + amp)? {
+                    Some(c) => Some((&*c.encode_utf8(&mut utf8), name.len())),
+                    None => defined.get(name).map(|value| (value.as_str(), name.len())),
+                }
+            }
+            None => None,
+        };
+
+        match resolved {
+            Some((replacement, name_len)) => {
+                decoded.push_str(&text[copied..amp]);
+                decoded.push_str(replacement);
+                copied = amp + name_len + 2;
+                search = copied;
+            }
+            None => {
+                if policy == EntityPolicy::Strict {
+                    return Err(ParseError::UnrecognizedEntity(position + amp));
+                }
+            }
+        }
+    }
+    decoded.push_str(&text[copied..]);
+
+    Ok(Cow::Owned(decoded))
+}
+
+/// The general entities the internal subset of `doctype` declares with a
+/// quoted value, `<!ENTITY name "value">`, by name. References in a value are
+/// replaced once when it is declared, as far as `decode` knows them. The first
+/// declaration of a name wins, as in XML, and parameter entities and external
+/// `SYSTEM` or `PUBLIC` ones are left out.
+pub fn internal_entities(doctype: &str) -> HashMap<String, String> {
+    let mut defined = HashMap::new();
+    let mut rest = match doctype.find('[') {
+        Some(open) => &doctype[open + 1..],
+        None => return defined,
+    };
+
+    while let Some(found) = rest.find("<!") {
+        rest = &rest[found..];
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let declaration = match rest.strip_prefix("<!ENTITY") {
+            Some(declaration) => declaration.trim_start(),
+            None => {
+                rest = &rest[2..];
+                continue;
+            }
+        };
+        rest = declaration;
+        if declaration.starts_with('%') {
+            continue;
+        }
+
+        let name_end = declaration
+            .find(|c: char| c.is_whitespace() || c == '"' || c == '\'')
+            .unwrap_or(declaration.len());
+        let (name, after_name) = declaration.split_at(name_end);
+        let after_name = after_name.trim_start();
+        rest = after_name;
+
+        let quote = match after_name.chars().next() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => continue,
+        };
+        let value = match after_name[1..].find(quote) {
+            Some(end) => &after_name[1..1 + end],
+            None => break,
+        };
+        rest = &after_name[1 + value.len() + 1..];
+
+        if !name.is_empty() && !defined.contains_key(name) {
+            let value = decode(value, 0, EntityPolicy::Lenient)
+                .map_or_else(|_| String::from(value), Cow::into_owned);
+            defined.insert(String::from(name), value);
+        }
+    }
+    defined
+}
+
+/// Escapes `&`, `<` and `>` so `text` can be written as element content.
+pub fn escape_text(text: &str) -> Cow<'_, str> {
+    escape(text, false)
+}
+
+/// Escapes `&`, `<`, `>` and `"` so `value` can be written inside a double
+/// quoted attribute.
+pub fn escape_attribute(value: &str) -> Cow<'_, str> {
+    escape(value, true)
+}
+
+fn escape(text: &str, quotes: bool) -> Cow<'_, str> {
+    let needs_escape = |c: char| matches!(c, '&' | '<' | '>') || (quotes && c == '"');
+    if !text.contains(needs_escape) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut escaped = String::with_capacity(text.len() + 8);
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' if quotes => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Resolves the name between `&` and `;`, `None` if it is not a reference.
+fn resolve(name: &str, position: usize) -> Result<Option<char>, ParseError> {
+    let c = match name {
+        "lt" => '<',
+        "gt" => '>',
+        "amp" => '&',
+        "quot" => '"',
+        "apos" => '\'',
+        _ => {
+            let digits = match name.strip_prefix('#') {
+                Some(digits) => digits,
+                None => return Ok(None),
+            };
+            let (digits, radix) = match digits.strip_prefix('x') {
+                Some(hex) => (hex, 16),
+                None => (digits, 10),
+            };
+            if digits.is_empty() || !digits.chars().all(|d| d.is_digit(radix)) {
+                return Ok(None);
+            }
+
+            // References too large for a u32 are reported as u32::MAX.
+            let value = digits
+                .chars()
+                .try_fold(0u32, |acc, d| {
+                    acc.checked_mul(radix)?.checked_add(d.to_digit(radix)?)
+                })
+                .unwrap_or(u32::MAX);
+
+            match char::from_u32(value) {
+                Some(c) => c,
+                None => return Err(ParseError::InvalidCharacterReference { value, position }),
+            }
+        }
+    };
+    Ok(Some(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_invalid_reference(text: &str, expected_value: u32, expected_position: usize) {
+        match decode(text, 10, EntityPolicy::Lenient) {
+            Ok(v) => panic!("Expected InvalidCharacterReference, got Ok({})", v),
+            Err(e) => match e {
+                ParseError::InvalidCharacterReference { value, position } => {
+                    assert_eq!(value, expected_value);
+                    assert_eq!(position, expected_position);
+                }
+                _ => panic!("Expected InvalidCharacterReference, got Err({:?})", e),
+            },
+        }
+    }
+
+    #[test]
+    fn test_decode_predefined_and_numeric() {
+        assert_eq!(
+            decode(
+                "a&lt;b&gt;&amp;&quot;&apos;&#65;&#x42;&#x1F600;",
+                0,
+                EntityPolicy::Lenient
+            )
+            .unwrap(),
+            "a<b>&\"'AB😀"
+        );
+    }
+
+    #[test]
+    fn test_decode_borrows_when_unchanged() {
+        assert!(matches!(
+            decode("plain text", 0, EntityPolicy::Lenient).unwrap(),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_decode_keeps_unrecognized_references() {
+        assert_eq!(
+            decode(
+                "Tom & Jerry &unknown; &#; &#xZZ; &amp",
+                0,
+                EntityPolicy::Lenient
+            )
+            .unwrap(),
+            "Tom & Jerry &unknown; &#; &#xZZ; &amp"
+        );
+    }
+
+    #[test]
+    fn test_decode_strict_rejects_unrecognized_references() {
+        for (text, expected) in [
+            ("Tom & Jerry", 4),
+            ("&unknown;", 0),
+            ("a&#xZZ;", 1),
+            ("&amp", 0),
+        ] {
+            match decode(text, 3, EntityPolicy::Strict) {
+                Ok(v) => panic!("Expected UnrecognizedEntity, got Ok({})", v),
+                Err(e) => match e {
+                    ParseError::UnrecognizedEntity(pos) => assert_eq!(pos, 3 + expected),
+                    _ => panic!("Expected UnrecognizedEntity, got Err({:?})", e),
+                },
+            }
+        }
+        assert_eq!(decode("&lt;&#65;", 0, EntityPolicy::Strict).unwrap(), "<A");
+    }
+
+    #[test]
+    fn test_decode_with_declared_entities() {
+        let defined = HashMap::from([(String::from("foo"), String::from("bar"))]);
+        assert_eq!(
+            decode_with("&foo;&lt;&foo;", 0, EntityPolicy::Strict, &defined).unwrap(),
+            "bar<bar"
+        );
+        match decode_with("&bar;", 5, EntityPolicy::Strict, &defined) {
+            Err(ParseError::UnrecognizedEntity(position)) => assert_eq!(position, 5),
+            result => panic!("Expected UnrecognizedEntity, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_internal_entities() {
+        let doctype = "<!DOCTYPE x [\n  <!ENTITY foo \"bar\">\n  <!ENTITY foo 'ignored'>\n  <!-- <!ENTITY commented \"no\"> -->\n  <!ELEMENT x ANY>\n  <!ENTITY % param \"no\">\n  <!ENTITY ext SYSTEM \"ext.xml\">\n  <!ENTITY copy '&#169; &amp; co'>\n]>";
+        let defined = internal_entities(doctype);
+        assert_eq!(defined.len(), 2);
+        assert_eq!(defined["foo"], "bar");
+        assert_eq!(defined["copy"], "© & co");
+
+        assert!(internal_entities("<!DOCTYPE html>").is_empty());
+        assert!(internal_entities("<!DOCTYPE x [ <!ENTITY foo \"open").is_empty());
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            escape_text("a < b & \"c\" > d"),
+            "a &lt; b &amp; \"c\" &gt; d"
+        );
+        assert_eq!(
+            escape_attribute("say \"hi\" & 'bye'"),
+            "say &quot;hi&quot; &amp; 'bye'"
+        );
+        assert!(matches!(escape_text("plain"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_decode_rejects_surrogate() {
+        assert_invalid_reference("ok &#xD800;", 0xD800, 13);
+    }
+
+    #[test]
+    fn test_decode_rejects_out_of_range() {
+        assert_invalid_reference("&#x110000;", 0x110000, 10);
+    }
+
+    #[test]
+    fn test_decode_rejects_huge_reference() {
+        assert_invalid_reference("&#99999999999999999999999;", u32::MAX, 10);
+        assert_invalid_reference("&#xFFFFFFFFFF;", u32::MAX, 10);
+    }
+}
@@ -1,9 +1,11 @@
 use std::error::Error;
 use std::fmt::Display;
+use std::io;
 
 #[derive(Debug)]
 pub enum TagParseError {
     UnterminatedStringLiteral(usize),
+    MalformedStringLiteral(usize),
     PeekOutOfBounds {
         offset: i64,
         cur_idx: usize,
@@ -15,7 +17,12 @@ pub enum TagParseError {
         current: String,
     },
     UnexpectedTagToken,
+    UnexpectedCharacter {
+        character: char,
+        position: usize,
+    },
     InvalidFirstToken,
+    EmptyTag,
 }
 impl Display for TagParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -23,6 +30,13 @@ impl Display for TagParseError {
             TagParseError::UnterminatedStringLiteral(loc) => {
                 write!(f, "Unterminated string literal, found at {}", loc)
             }
+            TagParseError::MalformedStringLiteral(loc) => {
+                write!(
+                    f,
+                    "String literal at {} does not start and end with the same quote",
+                    loc
+                )
+            }
             TagParseError::PeekOutOfBounds {
                 offset,
                 cur_idx,
@@ -48,12 +62,25 @@ impl Display for TagParseError {
             TagParseError::UnexpectedTagToken => {
                 write!(f, "Expected String token on the left and StringLiteral token on the right of Equals token")
             }
+            TagParseError::UnexpectedCharacter {
+                character,
+                position,
+            } => {
+                write!(
+                    f,
+                    "Unexpected character '{}' inside tag at {}",
+                    character, position
+                )
+            }
             TagParseError::InvalidFirstToken => {
                 write!(
                     f,
                     "First token of any tag should either be of type String or ForwardSlash"
                 )
             }
+            TagParseError::EmptyTag => {
+                write!(f, "Tag contains no name or attributes")
+            }
         }
     }
 }
@@ -63,7 +90,8 @@ impl Error for TagParseError {}
 pub enum ParseError {
     UnterminatedAngularBracket(usize),
     TagParseError(TagParseError),
-    NoTokensToParse,
+    /// The input ended, at the given offset, without any element.
+    NoTokensToParse(usize),
     InvalidFirstToken,
     UnexpectedClosingTag {
         expected: String,
@@ -74,6 +102,32 @@ pub enum ParseError {
         obtained: String,
         position: usize,
     },
+    InvalidCharacterReference {
+        value: u32,
+        position: usize,
+    },
+    UnrecognizedEntity(usize),
+    MalformedComment(usize),
+    MalformedProcessingInstruction(usize),
+    UnterminatedComment(usize),
+    UnterminatedCData(usize),
+    MisplacedDeclaration(usize),
+    /// A `<!DOCTYPE ...>` after the root element started.
+    MisplacedDoctype(usize),
+    /// An element or text found after the root element closed.
+    ContentOutsideRoot {
+        position: usize,
+    },
+    /// An element nested deeper than `ParseOptions::max_depth` allows.
+    MaxDepthExceeded {
+        depth: usize,
+        position: usize,
+    },
+    /// The input ended with elements still open, listed outermost first.
+    UnclosedTags {
+        names: Vec<String>,
+        positions: Vec<usize>,
+    },
 }
 
 impl Display for ParseError {
@@ -83,10 +137,14 @@ impl Display for ParseError {
                 write!(f, "Unterminated angular bracket, found at location {}", loc)
             }
             ParseError::TagParseError(internal_err) => {
-                write!(f, "{}", internal_err.to_string())
+                write!(f, "{}", internal_err)
             }
-            ParseError::NoTokensToParse => {
-                write!(f, "Lexer could not produce any tokens")
+            ParseError::NoTokensToParse(loc) => {
+                write!(
+                    f,
+                    "Document is empty, it holds no element, input ends at location {}",
+                    loc
+                )
             }
             ParseError::InvalidFirstToken => {
                 write!(f, "First token of xml document should be of type XMLTag")
@@ -109,8 +167,231 @@ impl Display for ParseError {
                     obtained, position
                 )
             }
+            ParseError::InvalidCharacterReference { value, position } => {
+                write!(
+                    f,
+                    "Character reference at position: {} refers to invalid character {:#x}",
+                    position, value
+                )
+            }
+            ParseError::UnrecognizedEntity(position) => {
+                write!(
+                    f,
+                    "'&' at position: {} does not start a recognized entity or character reference",
+                    position
+                )
+            }
+            ParseError::MalformedComment(position) => {
+                write!(
+                    f,
+                    "Malformed comment or markup declaration at position: {}, comments look like <!-- ... -->",
+                    position
+                )
+            }
+            ParseError::MalformedProcessingInstruction(position) => {
+                write!(
+                    f,
+                    "Malformed processing instruction at position: {}, expected <?target ... ?>",
+                    position
+                )
+            }
+            ParseError::UnterminatedComment(position) => {
+                write!(
+                    f,
+                    "Comment opened at position: {} is never closed with -->",
+                    position
+                )
+            }
+            ParseError::UnterminatedCData(position) => {
+                write!(
+                    f,
+                    "CDATA section opened at position: {} is never closed with ]]>",
+                    position
+                )
+            }
+            ParseError::MisplacedDeclaration(position) => {
+                write!(
+                    f,
+                    "XML declaration found at position: {}, it may only start the document",
+                    position
+                )
+            }
+            ParseError::MisplacedDoctype(position) => {
+                write!(
+                    f,
+                    "DOCTYPE declaration found at position: {}, it may only precede the root element",
+                    position
+                )
+            }
+            ParseError::ContentOutsideRoot { position } => {
+                write!(
+                    f,
+                    "Content at position: {} follows the root element, a document holds a single root",
+                    position
+                )
+            }
+            ParseError::MaxDepthExceeded { depth, position } => {
+                write!(
+                    f,
+                    "Element at position: {} is nested {} deep, more than the maximum allowed",
+                    position, depth
+                )
+            }
+            ParseError::UnclosedTags { names, positions } => {
+                let tags: Vec<String> = names
+                    .iter()
+                    .zip(positions)
+                    .map(|(name, position)| format!("{} at position: {}", name, position))
+                    .collect();
+                write!(f, "Input ended with tags left open: {}", tags.join(", "))
+            }
         }
     }
 }
 
 impl Error for ParseError {}
+
+impl ParseError {
+    /// Byte offset into the document the error was found at. `None` for
+    /// errors not tied to one place and for tag errors, whose positions are
+    /// relative to the tag.
+    pub fn position(&self) -> Option<usize> {
+        match self {
+            ParseError::UnterminatedAngularBracket(position)
+            | ParseError::UnexpectedClosingTag { position, .. }
+            | ParseError::ClosingTagNeverOpened { position, .. }
+            | ParseError::InvalidCharacterReference { position, .. }
+            | ParseError::UnrecognizedEntity(position)
+            | ParseError::MalformedComment(position)
+            | ParseError::MalformedProcessingInstruction(position)
+            | ParseError::UnterminatedComment(position)
+            | ParseError::UnterminatedCData(position)
+            | ParseError::MisplacedDeclaration(position)
+            | ParseError::MisplacedDoctype(position)
+            | ParseError::NoTokensToParse(position)
+            | ParseError::ContentOutsideRoot { position }
+            | ParseError::MaxDepthExceeded { position, .. } => Some(*position),
+            // The innermost tag, the one whose closing tag was due first.
+            ParseError::UnclosedTags { positions, .. } => positions.last().copied(),
+            ParseError::TagParseError(_) | ParseError::InvalidFirstToken => None,
+        }
+    }
+    /// Pairs the error with the line and column of its position in `source`,
+    /// which must be the document that was parsed.
+    pub fn locate(self, source: &str) -> LocatedParseError {
+        let location = self
+            .position()
+            .map(|position| crate::offset_to_line_col(source, position));
+        LocatedParseError {
+            error: self,
+            location,
+        }
+    }
+}
+
+/// A `ParseError` with the 1-based line and column it was found at, see
+/// `ParseError::locate`.
+#[derive(Debug)]
+pub struct LocatedParseError {
+    pub error: ParseError,
+    pub location: Option<(usize, usize)>,
+}
+
+impl Display for LocatedParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.location {
+            Some((line, column)) => {
+                write!(f, "{} (line {}, column {})", self.error, line, column)
+            }
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+impl Error for LocatedParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[derive(Debug)]
+pub enum ValidationError {
+    TextNotAllowed {
+        tag: String,
+        position: usize,
+    },
+    ChildNotAllowed {
+        tag: String,
+        child: String,
+        position: usize,
+    },
+    InvalidName(String),
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::TextNotAllowed { tag, position } => {
+                write!(
+                    f,
+                    "Element: {}, found at position: {} is not allowed to contain text",
+                    tag, position
+                )
+            }
+            ValidationError::ChildNotAllowed {
+                tag,
+                child,
+                position,
+            } => {
+                write!(
+                    f,
+                    "Element: {}, found at position: {} is not allowed inside element: {}",
+                    child, position, tag
+                )
+            }
+            ValidationError::InvalidName(name) => {
+                write!(f, "'{}' is not a valid XML name", name)
+            }
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+/// Failure of an operation that both parses a document and does I/O, like
+/// `pretty_print_to_file` or `RXML::parse_file`.
+#[derive(Debug)]
+pub enum FormatError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+impl Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatError::Io(e) => write!(f, "I/O error: {}", e),
+            FormatError::Parse(e) => write!(f, "Parse error: {}", e),
+        }
+    }
+}
+
+impl Error for FormatError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FormatError::Io(e) => Some(e),
+            FormatError::Parse(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for FormatError {
+    fn from(e: io::Error) -> Self {
+        FormatError::Io(e)
+    }
+}
+
+impl From<ParseError> for FormatError {
+    fn from(e: ParseError) -> Self {
+        FormatError::Parse(e)
+    }
+}
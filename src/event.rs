@@ -0,0 +1,16 @@
+use std::collections::HashMap;
+
+/// A single step of a document read start to finish, the flat counterpart of
+/// the `XMLNode` tree.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    StartElement {
+        name: String,
+        attributes: HashMap<String, String>,
+    },
+    EndElement {
+        name: String,
+    },
+    Text(String),
+    Eof,
+}
@@ -1,23 +1,1002 @@
 mod api;
+pub mod declaration;
+mod entities;
 pub mod error;
+pub mod event;
+pub mod namespace;
+pub mod options;
 mod parsedoc;
 mod parsetag;
+pub mod schema;
+mod select;
+pub mod stats;
+pub mod token;
+mod uri;
+pub mod writer;
 
-use api::XMLNode;
-use error::ParseError;
+pub use api::{XMLNode, XMLNodeBuilder, XMLTag};
+pub use declaration::sniff_encoding;
+use declaration::XmlDeclaration;
+use error::{FormatError, ParseError};
+use event::Event;
+use options::{CompareOptions, ParseOptions};
 use parsedoc::XMLParser;
+pub use parsetag::TagKind;
+use stats::DocumentStats;
+use std::convert::Infallible;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read};
+use std::path::Path;
 use std::rc::Rc;
+use std::str::FromStr;
+use token::TokenKind;
+use writer::PrettyPrinter;
 
 pub struct RXML {
     content: String,
+    options: ParseOptions,
 }
 
 impl RXML {
     pub fn new(content: String) -> Self {
-        Self { content }
+        Self::with_options(content, ParseOptions::default())
+    }
+    pub fn with_options(content: String, options: ParseOptions) -> Self {
+        Self { content, options }
+    }
+    /// Like `new` but accepting bare and unquoted attributes, see
+    /// `ParseOptions::lenient_attributes`.
+    pub fn new_lenient(content: String) -> Self {
+        let options = ParseOptions {
+            lenient_attributes: true,
+            ..Default::default()
+        };
+        Self::with_options(content, options)
+    }
+    /// Reads the UTF-8 file at `path`, dropping a leading byte order mark.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Ok(Self::new(strip_bom(content)))
+    }
+    /// Reads `reader` to its end, like `from_file` but for pipes, sockets and
+    /// the like. The whole input is still buffered in memory before parsing.
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        Ok(Self::new(strip_bom(content)))
+    }
+    /// Reads and parses the file at `path` in one go.
+    pub fn parse_file<P: AsRef<Path>>(path: P) -> Result<Rc<XMLNode>, FormatError> {
+        Ok(Self::from_file(path)?.parse()?)
     }
     pub fn parse(&self) -> Result<Rc<XMLNode>, ParseError> {
-        let parser = XMLParser::new(self.content.as_str());
-        return Ok(parser.parse()?);
+        let parser = XMLParser::with_options(self.content.as_str(), &self.options);
+        parser.parse()
+    }
+    /// Reads the content as a stream of events without building the tree,
+    /// see `Events` for how text and nesting are handled.
+    pub fn events(&self) -> impl Iterator<Item = Result<Event, ParseError>> + '_ {
+        XMLParser::with_options(self.content.as_str(), &self.options).events()
+    }
+    /// Splits the content into tokens, each with its kind and the byte range
+    /// it spans, for tooling that works on the source itself. Nothing is
+    /// checked beyond what it takes to find where a token ends, the stream
+    /// ends with the input or with the first error.
+    pub fn tokens(
+        &self,
+    ) -> impl Iterator<Item = Result<(TokenKind, usize, usize), ParseError>> + '_ {
+        XMLParser::with_options(self.content.as_str(), &self.options).tokens()
+    }
+    /// Parses past the errors it can recover from, returning every error
+    /// found and whatever tree could be built, see `XMLParser::parse_all` for
+    /// which errors those are.
+    pub fn parse_all(&self) -> (Option<Rc<XMLNode>>, Vec<ParseError>) {
+        let parser = XMLParser::with_options(self.content.as_str(), &self.options);
+        parser.parse_all()
+    }
+    /// The XML declaration the content starts with, `None` when there is none
+    /// or it lacks a version.
+    pub fn declaration(&self) -> Option<XmlDeclaration> {
+        XMLParser::new(self.content.as_str()).declaration()
+    }
+    /// Computes `DocumentStats` in one streaming pass without keeping the tree
+    /// in memory.
+    pub fn parse_stats_only(&self) -> Result<DocumentStats, ParseError> {
+        let parser = XMLParser::with_options(self.content.as_str(), &self.options);
+        parser.stats()
+    }
+    /// Parses the content as a fragment and appends the resulting nodes to
+    /// `parent`, see `XMLParser::parse_into`.
+    pub fn parse_into(&self, parent: &Rc<XMLNode>) -> Result<(), ParseError> {
+        let parser = XMLParser::with_options(self.content.as_str(), &self.options);
+        parser.parse_into(parent)
+    }
+}
+
+impl From<String> for RXML {
+    fn from(content: String) -> Self {
+        Self::new(content)
+    }
+}
+
+impl From<&str> for RXML {
+    fn from(content: &str) -> Self {
+        Self::new(String::from(content))
+    }
+}
+
+/// Like `From<&str>`, nothing is parsed until `parse` is called, so this
+/// never fails.
+impl FromStr for RXML {
+    type Err = Infallible;
+
+    fn from_str(content: &str) -> Result<Self, Self::Err> {
+        Ok(Self::from(content))
+    }
+}
+
+/// A parser meant to be kept around and fed many documents, reusing its
+/// internal buffers between calls instead of allocating them per document:
+/// the stack of open elements and the buffer tags are lexed into. Both are
+/// emptied after every document, so nothing carries over but their capacity.
+#[derive(Default)]
+pub struct Parser {
+    options: ParseOptions,
+    node_stack: Vec<Rc<XMLNode>>,
+    tag_tokens: Vec<parsetag::TagToken<'static>>,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self::with_options(ParseOptions::default())
+    }
+    /// A parser applying `options` to every document it is given.
+    pub fn with_options(options: ParseOptions) -> Self {
+        Self {
+            options,
+            node_stack: Vec::new(),
+            tag_tokens: Vec::new(),
+        }
+    }
+    pub fn parse(&mut self, input: &str) -> Result<Rc<XMLNode>, ParseError> {
+        let parser = XMLParser::with_options(input, &self.options)
+            .reusing(std::mem::take(&mut self.tag_tokens));
+        let result = parser.parse_with_stack(&mut self.node_stack);
+        self.tag_tokens = parser.take_tag_tokens();
+        self.node_stack.clear();
+        result
+    }
+}
+
+/// Checks that `input` parses, streaming through it like `RXML::events`
+/// rather than building the tree. Returns the first error found.
+pub fn validate_well_formed(input: &str) -> Result<(), ParseError> {
+    for event in XMLParser::new(input).events() {
+        event?;
+    }
+    Ok(())
+}
+
+fn strip_bom(mut content: String) -> String {
+    if content.starts_with('\u{FEFF}') {
+        content.drain(..'\u{FEFF}'.len_utf8());
+    }
+    content
+}
+
+/// Parses `input` and writes it formatted by `printer` to the file at `path`,
+/// creating or truncating it. Nothing is written when `input` does not parse.
+pub fn pretty_print_to_file(
+    input: &str,
+    path: impl AsRef<Path>,
+    printer: &PrettyPrinter,
+) -> Result<(), FormatError> {
+    let root = XMLParser::new(input).parse()?;
+    let file = BufWriter::new(File::create(path)?);
+    printer.write(&root, file)?;
+    Ok(())
+}
+
+/// Converts a byte offset as carried by `ParseError` into a 1-based line and
+/// column, the column counting characters rather than bytes. Offsets past the
+/// end resolve to the end of `source` and offsets inside a multibyte character
+/// to that character.
+pub fn offset_to_line_col(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let before = &source.as_bytes()[..offset];
+
+    let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+    let line_start = match before.iter().rposition(|&b| b == b'\n') {
+        Some(newline) => newline + 1,
+        None => 0,
+    };
+    let column = source[line_start..]
+        .char_indices()
+        .take_while(|(i, c)| line_start + i + c.len_utf8() <= offset)
+        .count()
+        + 1;
+
+    (line, column)
+}
+
+/// Parses both documents and compares the resulting trees.
+///
+/// Tag names, attribute names and values, element order and text are compared.
+/// Ignored are source positions, the order attributes were written in, leading
+/// and trailing whitespace of a node's text and the length of whitespace runs
+/// inside it (`" a   b "` equals `"a b"`).
+pub fn semantically_equal(a: &str, b: &str) -> Result<bool, ParseError> {
+    let left = XMLParser::new(a).parse()?;
+    let right = XMLParser::new(b).parse()?;
+    let options = CompareOptions {
+        whitespace: true,
+        ..Default::default()
+    };
+    Ok(left.equals_ignoring(&right, &options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::TagParseError;
+    use options::TextMode;
+
+    #[test]
+    fn test_bare_ampersand_in_text() {
+        let text = String::from("<p>Tom & Jerry &amp; co</p>");
+
+        let lenient = RXML::new(text.clone()).parse().unwrap();
+        assert_eq!(*lenient.content.borrow(), "Tom & Jerry & co");
+
+        let strict_options = ParseOptions {
+            entity_policy: options::EntityPolicy::Strict,
+            ..Default::default()
+        };
+        match RXML::with_options(text, strict_options).parse() {
+            Ok(node) => panic!("Expected UnrecognizedEntity, got node: {:?}", node),
+            Err(e) => match e {
+                ParseError::UnrecognizedEntity(pos) => assert_eq!(pos, 7),
+                _ => panic!("Expected UnrecognizedEntity, got Err({:?})", e),
+            },
+        }
+    }
+
+    #[test]
+    fn test_tag_name_transform_lowercases_names() {
+        let text = String::from("<DIV Class='x'><Span>hi</SPAN></div>");
+
+        match RXML::new(text.clone()).parse() {
+            Ok(node) => panic!("Expected UnexpectedClosingTag, got node: {:?}", node),
+            Err(e) => match e {
+                ParseError::UnexpectedClosingTag { .. } => {}
+                _ => panic!("Expected UnexpectedClosingTag, got Err({:?})", e),
+            },
+        }
+
+        let options = ParseOptions {
+            tag_name_transform: Some(Box::new(str::to_lowercase)),
+            ..Default::default()
+        };
+        let root = RXML::with_options(text, options).parse().unwrap();
+        assert_eq!(root.tag.name, "div");
+        assert_eq!(root.children.borrow()[0].tag.name, "span");
+        assert_eq!(root.attribute_map_ref()["Class"], "x");
+    }
+
+    #[test]
+    fn test_attribute_multimap_keeps_repeated_values() {
+        let text = String::from("<row tag='a' id='1' tag='b&amp;c' tag='d'></row>");
+
+        let root = RXML::new(text.clone()).parse().unwrap();
+        assert_eq!(*root.get_all_attributes("tag"), ["d"]);
+
+        let options = ParseOptions {
+            attribute_multimap: true,
+            ..Default::default()
+        };
+        let root = RXML::with_options(text, options).parse().unwrap();
+        assert_eq!(*root.get_all_attributes("tag"), ["a", "b&c", "d"]);
+        assert_eq!(*root.get_all_attributes("id"), ["1"]);
+        assert!(root.get_all_attributes("missing").is_empty());
+        assert_eq!(root.attribute_map_ref()["tag"], "d");
+
+        root.set_attribute("tag", "e");
+        assert_eq!(*root.get_all_attributes("tag"), ["e"]);
+    }
+
+    #[test]
+    fn test_base_uri_resolves_uri_attributes() {
+        let text = String::from(
+            "<page><a href='../about.html#team'>x</a><img src='img/logo.png' alt='logo.png'></img><a href='https://other.org/'>y</a><link rel='up' data='up.html'></link></page>",
+        );
+        let options = ParseOptions {
+            base_uri: Some(String::from("https://example.com/docs/guide/index.html")),
+            ..Default::default()
+        };
+        let root = RXML::with_options(text.clone(), options).parse().unwrap();
+        let attribute = |index: usize, name: &str| {
+            root.children.borrow()[index].attribute_map_ref()[name].clone()
+        };
+
+        assert_eq!(
+            attribute(0, "href"),
+            "https://example.com/docs/about.html#team"
+        );
+        assert_eq!(
+            attribute(1, "src"),
+            "https://example.com/docs/guide/img/logo.png"
+        );
+        assert_eq!(attribute(1, "alt"), "logo.png");
+        assert_eq!(attribute(2, "href"), "https://other.org/");
+        assert_eq!(attribute(3, "data"), "up.html");
+
+        let options = ParseOptions {
+            base_uri: Some(String::from("https://example.com/docs/")),
+            uri_attributes: vec![String::from("data")],
+            ..Default::default()
+        };
+        let root = RXML::with_options(text, options).parse().unwrap();
+        assert_eq!(
+            root.children.borrow()[3].attribute_map_ref()["data"],
+            "https://example.com/docs/up.html"
+        );
+        assert_eq!(
+            root.children.borrow()[0].attribute_map_ref()["href"],
+            "../about.html#team"
+        );
+    }
+
+    #[test]
+    fn test_declaration() {
+        let with = RXML::new(String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<!-- prolog -->\n<?xml-stylesheet href='a.xsl'?>\n<root><a>1</a></root>",
+        ));
+        assert_eq!(
+            with.declaration(),
+            Some(XmlDeclaration {
+                version: String::from("1.0"),
+                encoding: Some(String::from("UTF-8")),
+                standalone: Some(true),
+            })
+        );
+        let root = with.parse().unwrap();
+        assert_eq!(root.tag.name, "root");
+        assert_eq!(root.children.borrow()[0].text_content(), "1");
+
+        let without = RXML::new(String::from("<root><a>1</a></root>"));
+        assert_eq!(without.declaration(), None);
+        assert_eq!(without.parse().unwrap().canonicalize(), root.canonicalize());
+    }
+
+    #[test]
+    fn test_declaration_must_come_first() {
+        for (text, expected) in [
+            ("<!-- c --><?xml version='1.0'?><a></a>", 10),
+            ("<a><?xml version='1.0'?></a>", 3),
+            (" <?xml version='1.0'?><a></a>", 1),
+        ] {
+            match RXML::new(String::from(text)).parse() {
+                Ok(node) => panic!("Expected MisplacedDeclaration, got node: {:?}", node),
+                Err(e) => match e {
+                    ParseError::MisplacedDeclaration(pos) => assert_eq!(pos, expected),
+                    _ => panic!("Expected MisplacedDeclaration, got Err({:?})", e),
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_documents() {
+        for (text, line_col) in [("", (1, 1)), ("   ", (1, 4)), ("\n\n", (3, 1))] {
+            let document = RXML::new(String::from(text));
+            match document.parse() {
+                Ok(node) => panic!("Expected NoTokensToParse, got node: {:?}", node),
+                Err(e) => match e {
+                    ParseError::NoTokensToParse(position) => {
+                        assert_eq!(position, text.len());
+                        assert_eq!(e.locate(text).location, Some(line_col));
+                    }
+                    _ => panic!("Expected NoTokensToParse for {:?}, got Err({:?})", text, e),
+                },
+            }
+            assert!(matches!(
+                document.events().next(),
+                Some(Err(ParseError::NoTokensToParse(_)))
+            ));
+            assert!(document.declaration().is_none());
+            assert!(validate_well_formed(text).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_all_collects_errors() {
+        let text = "<doc><br x=/><p>&#xD800; ok</p><!bad><b>x</c></b></doc><extra/>";
+        let (root, errors) = RXML::new(String::from(text)).parse_all();
+
+        let kinds: Vec<&str> = errors
+            .iter()
+            .map(|e| match e {
+                ParseError::TagParseError(_) => "tag",
+                ParseError::InvalidCharacterReference { .. } => "reference",
+                ParseError::MalformedComment(_) => "comment",
+                ParseError::UnexpectedClosingTag { .. } => "closing",
+                ParseError::ContentOutsideRoot { .. } => "outside",
+                _ => panic!("Unexpected error {:?}", e),
+            })
+            .collect();
+        assert_eq!(kinds, ["tag", "reference", "comment", "closing", "outside"]);
+
+        let root = root.unwrap();
+        assert_eq!(
+            root.canonicalize(),
+            "<doc><p>&amp;#xD800; ok</p><b>x</b></doc>"
+        );
+        assert!(RXML::new(String::from(text)).parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_all_stops_at_unrecoverable_errors() {
+        let (root, errors) = RXML::new(String::from("<doc><a></doc>")).parse_all();
+        assert_eq!(root.unwrap().canonicalize(), "<doc><a></a></doc>");
+        assert!(matches!(
+            errors[..],
+            [ParseError::UnexpectedClosingTag { .. }]
+        ));
+
+        let (root, errors) = RXML::new(String::from("<doc><a>1</a></x><b")).parse_all();
+        assert_eq!(root.unwrap().canonicalize(), "<doc><a>1</a></doc>");
+        assert!(matches!(
+            errors[..],
+            [
+                ParseError::UnexpectedClosingTag { .. },
+                ParseError::UnterminatedAngularBracket(_)
+            ]
+        ));
+
+        let (root, errors) = RXML::new(String::from("<doc><a>")).parse_all();
+        assert!(root.is_some());
+        assert!(matches!(errors[..], [ParseError::UnclosedTags { .. }]));
+
+        let (root, errors) = RXML::new(String::new()).parse_all();
+        assert!(root.is_none());
+        assert!(matches!(errors[..], [ParseError::NoTokensToParse(0)]));
+
+        let (root, errors) = RXML::new(String::from("<a><b/></a>")).parse_all();
+        assert!(errors.is_empty());
+        assert_eq!(root.unwrap().canonicalize(), "<a><b></b></a>");
+    }
+
+    #[test]
+    fn test_case_insensitive_tags() {
+        let text = String::from("<Root><Child>x</CHILD><b/></root>");
+
+        match RXML::new(text.clone()).parse() {
+            Ok(node) => panic!("Expected UnexpectedClosingTag, got node: {:?}", node),
+            Err(e) => match e {
+                ParseError::UnexpectedClosingTag {
+                    expected, obtained, ..
+                } => {
+                    assert_eq!(expected, "Child");
+                    assert_eq!(obtained, "CHILD");
+                }
+                _ => panic!("Expected UnexpectedClosingTag, got Err({:?})", e),
+            },
+        }
+
+        let options = ParseOptions {
+            case_insensitive_tags: true,
+            ..Default::default()
+        };
+        let document = RXML::with_options(text, options);
+        let root = document.parse().unwrap();
+        assert_eq!(root.tag.name, "Root");
+        assert_eq!(root.children.borrow()[0].tag.name, "Child");
+        assert!(document.parse_stats_only().is_ok());
+        assert!(document.events().all(|event| event.is_ok()));
+
+        let mismatched = RXML::with_options(
+            String::from("<a></b>"),
+            ParseOptions {
+                case_insensitive_tags: true,
+                ..Default::default()
+            },
+        );
+        assert!(mismatched.parse().is_err());
+    }
+
+    #[test]
+    fn test_events() {
+        use std::collections::HashMap;
+
+        let text = String::from(
+            "<?xml version='1.0'?>\n<!-- c --><lib n='a&amp;b'><book>Dune <![CDATA[<1>]]></book><x/>\n</lib>\n",
+        );
+        let attributes = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<String, String>>()
+        };
+        let events: Vec<Event> = RXML::new(text.clone())
+            .events()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(
+            events,
+            [
+                Event::StartElement {
+                    name: String::from("lib"),
+                    attributes: attributes(&[("n", "a&b")]),
+                },
+                Event::StartElement {
+                    name: String::from("book"),
+                    attributes: attributes(&[]),
+                },
+                Event::Text(String::from("Dune <1>")),
+                Event::EndElement {
+                    name: String::from("book"),
+                },
+                Event::StartElement {
+                    name: String::from("x"),
+                    attributes: attributes(&[]),
+                },
+                Event::EndElement {
+                    name: String::from("x"),
+                },
+                Event::Text(String::from("\n")),
+                Event::EndElement {
+                    name: String::from("lib"),
+                },
+                Event::Eof,
+            ]
+        );
+        let tree = RXML::new(text).parse().unwrap().to_events();
+        assert_eq!(events[..events.len() - 1], tree[..]);
+
+        let mismatched = RXML::new(String::from("<a><b></a>"));
+        let mut events = mismatched.events();
+        assert!(matches!(
+            events.next(),
+            Some(Ok(Event::StartElement { .. }))
+        ));
+        assert!(matches!(
+            events.next(),
+            Some(Ok(Event::StartElement { .. }))
+        ));
+        assert!(matches!(
+            events.next(),
+            Some(Err(ParseError::UnexpectedClosingTag { .. }))
+        ));
+        assert!(events.next().is_none());
+
+        let last = RXML::new(String::from("<a><b/>")).events().last();
+        assert!(matches!(last, Some(Err(ParseError::UnclosedTags { .. }))));
+    }
+
+    #[test]
+    fn test_tokens() {
+        let doc = RXML::new(String::from(
+            "<?xml version='1.0'?>\n<a x='1'>hi  <!-- c --><b/><![CDATA[<]]></a>",
+        ));
+        let tokens: Vec<(TokenKind, usize, usize)> = doc.tokens().map(|t| t.unwrap()).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenKind::ProcessingInstruction, 0, 21),
+                (TokenKind::Whitespace, 21, 22),
+                (TokenKind::StartTag, 22, 31),
+                (TokenKind::Text, 31, 35),
+                (TokenKind::Comment, 35, 45),
+                (TokenKind::EmptyTag, 45, 49),
+                (TokenKind::CData, 49, 62),
+                (TokenKind::EndTag, 62, 66),
+            ]
+        );
+
+        let doc = RXML::new(String::from("<!DOCTYPE a><a>x</b>"));
+        let kinds: Vec<TokenKind> = doc.tokens().map(|t| t.unwrap().0).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Doctype,
+                TokenKind::StartTag,
+                TokenKind::Text,
+                TokenKind::EndTag,
+            ]
+        );
+
+        let doc = RXML::new(String::from("<a>x<b"));
+        let tokens: Vec<_> = doc.tokens().collect();
+        assert_eq!(tokens.len(), 3);
+        match &tokens[2] {
+            Err(ParseError::UnterminatedAngularBracket(position)) => assert_eq!(*position, 4),
+            result => panic!("Expected UnterminatedAngularBracket, got {:?}", result),
+        }
+
+        // Tags are not parsed, a malformed one is still a token of its own.
+        let doc = RXML::new(String::from("<a x=>t</a>"));
+        let tokens: Vec<(TokenKind, usize, usize)> = doc.tokens().map(|t| t.unwrap()).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenKind::StartTag, 0, 6),
+                (TokenKind::Text, 6, 7),
+                (TokenKind::EndTag, 7, 11),
+            ]
+        );
+        assert!(doc.parse().is_err());
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let nested = |depth: usize| "<a>".repeat(depth) + &"</a>".repeat(depth);
+        assert!(RXML::new(nested(options::DEFAULT_MAX_DEPTH))
+            .parse()
+            .is_ok());
+        match RXML::new(nested(options::DEFAULT_MAX_DEPTH + 1)).parse() {
+            Err(ParseError::MaxDepthExceeded { depth, position }) => {
+                assert_eq!(depth, options::DEFAULT_MAX_DEPTH + 1);
+                assert_eq!(position, 3 * options::DEFAULT_MAX_DEPTH);
+            }
+            result => panic!("Expected MaxDepthExceeded, got {:?}", result),
+        }
+
+        let options = || ParseOptions {
+            max_depth: 3,
+            ..Default::default()
+        };
+        let doc = RXML::with_options(String::from("<a><b><c/></b><d>x</d></a>"), options());
+        assert!(doc.parse().is_ok());
+        assert!(doc.events().all(|e| e.is_ok()));
+        assert_eq!(doc.parse_stats_only().unwrap().max_depth, 3);
+
+        // Unclosed tags are still counted, and the error is not recovered from.
+        let doc = RXML::with_options(String::from("<a><b><c><d/></c></b></a>"), options());
+        for result in [
+            doc.parse().map(|_| ()),
+            doc.parse_stats_only().map(|_| ()),
+            doc.events().find_map(|e| e.err()).map_or(Ok(()), Err),
+            doc.parse_all().1.into_iter().next().map_or(Ok(()), Err),
+        ] {
+            match result {
+                Err(ParseError::MaxDepthExceeded { depth, position }) => {
+                    assert_eq!((depth, position), (4, 9));
+                }
+                result => panic!("Expected MaxDepthExceeded, got {:?}", result),
+            }
+        }
+        let (root, errors) =
+            RXML::with_options(String::from("<a><b><c><d>"), options()).parse_all();
+        assert_eq!(root.unwrap().canonicalize(), "<a><b><c></c></b></a>");
+        assert!(matches!(errors[..], [ParseError::MaxDepthExceeded { .. }]));
+    }
+
+    #[test]
+    fn test_text_mode() {
+        let parse = |text: &str, text_mode: TextMode| {
+            let options = ParseOptions {
+                text_mode,
+                ..Default::default()
+            };
+            RXML::with_options(String::from(text), options)
+                .parse()
+                .unwrap()
+        };
+        let text = "<p>  hello   world  </p>";
+
+        assert_eq!(
+            *parse(text, TextMode::Preserve).content.borrow(),
+            "  hello   world  "
+        );
+        assert_eq!(
+            *parse(text, TextMode::Trim).content.borrow(),
+            "hello   world"
+        );
+        assert_eq!(
+            *parse(text, TextMode::Collapse).content.borrow(),
+            "hello world"
+        );
+
+        let mixed = parse("<p>\n  one  <b> x </b>\t two\n</p>", TextMode::Collapse);
+        assert_eq!(mixed.first_text().as_deref(), Some("one "));
+        assert_eq!(*mixed.content.borrow(), "one  two");
+        assert_eq!(*mixed.children.borrow()[0].content.borrow(), "x");
+        assert_eq!(mixed.text_content(), "one x two");
+    }
+
+    #[test]
+    fn test_lenient_attributes() {
+        let text = String::from("<table border><td colspan=2 class='x' nowrap>a</td></table>");
+
+        match RXML::new(text.clone()).parse() {
+            Ok(node) => panic!("Expected UnexpectedTagToken, got node: {:?}", node),
+            Err(e) => match e {
+                ParseError::TagParseError(TagParseError::UnexpectedTagToken) => (),
+                _ => panic!("Expected UnexpectedTagToken, got Err({:?})", e),
+            },
+        }
+        let strict = RXML::new(String::from("<input disabled name='q'/>"))
+            .parse()
+            .unwrap();
+        assert!(!strict.has_attr("disabled"));
+
+        let root = RXML::new_lenient(text).parse().unwrap();
+        assert_eq!(root.attr("border").as_deref(), Some(""));
+        let td = root.find_first("td").unwrap();
+        assert_eq!(td.attr("colspan").as_deref(), Some("2"));
+        assert_eq!(td.attr("class").as_deref(), Some("x"));
+        assert_eq!(td.attr("nowrap").as_deref(), Some(""));
+        assert_eq!(td.attribute_count(), 3);
+
+        let input = RXML::new_lenient(String::from("<input disabled name=q/>"))
+            .parse()
+            .unwrap();
+        assert_eq!(input.attr("disabled").as_deref(), Some(""));
+        assert_eq!(input.attr("name").as_deref(), Some("q"));
+
+        let td = RXML::new_lenient(String::from("<td colspan=2/>"))
+            .parse()
+            .unwrap();
+        assert_eq!(td.attr("colspan").as_deref(), Some("2"));
+        assert!(!td.has_children());
+
+        let link = RXML::new_lenient(String::from("<a href=http://x/y>go</a>"))
+            .parse()
+            .unwrap();
+        assert_eq!(link.attr("href").as_deref(), Some("http://x/y"));
+        assert_eq!(link.attribute_count(), 1);
+    }
+
+    #[test]
+    fn test_reusable_parser_does_not_leak_state() {
+        let documents = [
+            "<a x='1' y='2' z='3'><b><c>1</c></b></a>",
+            "<x><y><z></y></x>",
+            "<a><b c='1' c='2' =></b></a>",
+            "<d>2</d>",
+            "<e f=\"g\"/>",
+            "",
+        ];
+        let mut parser = Parser::new();
+        for _ in 0..2 {
+            for text in documents {
+                let fresh = XMLParser::new(text).parse();
+                match (parser.parse(text), fresh) {
+                    (Ok(reused), Ok(fresh)) => assert_eq!(reused, fresh, "{}", text),
+                    (Err(reused), Err(fresh)) => {
+                        assert_eq!(format!("{:?}", reused), format!("{:?}", fresh))
+                    }
+                    (reused, fresh) => panic!("{}: {:?} but {:?}", text, reused, fresh),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_reusable_parser_with_options() {
+        let mut parser = Parser::with_options(ParseOptions {
+            lenient_attributes: true,
+            case_insensitive_tags: true,
+            ..ParseOptions::default()
+        });
+        for _ in 0..2 {
+            let root = parser.parse("<TD colspan=2 nowrap>x</td>").unwrap();
+            assert_eq!(root.attr("colspan").as_deref(), Some("2"));
+            assert_eq!(root.attr("nowrap").as_deref(), Some(""));
+        }
+        assert!(Parser::new().parse("<td colspan=2>x</td>").is_err());
+    }
+
+    /// Times parsing many small documents with one `Parser` against a new
+    /// parser per document. A timing rather than a benchmark harness, which
+    /// would need nightly or another dependency; run it with
+    /// `cargo test --release bench_reusable_parser -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_reusable_parser() {
+        let text = "<order id='7' status='open'><item sku='a1' qty='2'>Pen</item><item sku='b2' qty='1'>Ink</item><note>rush</note></order>";
+        let rounds = 100_000;
+
+        let start = std::time::Instant::now();
+        for _ in 0..rounds {
+            XMLParser::new(text).parse().unwrap();
+        }
+        let fresh = start.elapsed();
+
+        let mut parser = Parser::new();
+        let start = std::time::Instant::now();
+        for _ in 0..rounds {
+            parser.parse(text).unwrap();
+        }
+        let reused = start.elapsed();
+
+        println!("{} parses: {:?} fresh, {:?} reused", rounds, fresh, reused);
+    }
+
+    #[test]
+    fn test_offset_to_line_col() {
+        let source = "<a>\n  <b>é</b>\n</a>";
+
+        assert_eq!(offset_to_line_col(source, 0), (1, 1));
+        assert_eq!(offset_to_line_col(source, 3), (1, 4));
+        assert_eq!(offset_to_line_col(source, 4), (2, 1));
+        assert_eq!(offset_to_line_col(source, 6), (2, 3));
+        // `é` is two bytes, so `</b>` starts in column 7 but at byte 11.
+        assert_eq!(offset_to_line_col(source, 11), (2, 7));
+        assert_eq!(offset_to_line_col(source, 9), (2, 6));
+        assert_eq!(offset_to_line_col(source, 10), (2, 6));
+        assert_eq!(offset_to_line_col(source, source.len()), (3, 5));
+        assert_eq!(offset_to_line_col(source, 1000), (3, 5));
+        assert_eq!(offset_to_line_col("", 5), (1, 1));
+    }
+
+    #[test]
+    fn test_parse_errors_locate_line_and_column() {
+        let source = "<doc>\n  <item>\n    <name>x</nam>\n  </item>\n</doc>";
+
+        let located = match RXML::new(String::from(source)).parse() {
+            Ok(node) => panic!("Expected UnexpectedClosingTag, got node: {:?}", node),
+            Err(e) => e.locate(source),
+        };
+        assert_eq!(located.error.position(), Some(19));
+        assert_eq!(located.location, Some((3, 5)));
+        assert!(located.to_string().ends_with("(line 3, column 5)"));
+
+        let located = ParseError::InvalidFirstToken.locate(source);
+        assert_eq!(located.location, None);
+        assert_eq!(
+            located.to_string(),
+            ParseError::InvalidFirstToken.to_string()
+        );
+    }
+
+    #[test]
+    fn test_pretty_print_to_file_round_trip() {
+        let input = "<config env='prod'><db host='localhost' port='5432'></db><flags><on/>beta</flags><p>Hello <b>brave</b> world</p></config>";
+        let path = std::env::temp_dir().join(format!("rxml-pretty-{}.xml", std::process::id()));
+
+        pretty_print_to_file(input, &path, &PrettyPrinter::new()).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            written,
+            "<config env=\"prod\">\n  <db host=\"localhost\" port=\"5432\"/>\n  <flags><on/>beta</flags>\n  <p>Hello <b>brave</b> world</p>\n</config>"
+        );
+        // Only the indentation between elements is new, every other piece of
+        // text reads back as it was.
+        let events = |text: &str| -> Vec<Event> {
+            let root = RXML::new(String::from(text)).parse().unwrap();
+            root.to_events()
+                .into_iter()
+                .filter(|event| !matches!(event, Event::Text(t) if t.trim().is_empty()))
+                .collect()
+        };
+        assert_eq!(events(input), events(&written));
+
+        match pretty_print_to_file("<a></b>", &path, &PrettyPrinter::new()) {
+            Ok(()) => panic!("Expected a parse error"),
+            Err(e) => match e {
+                FormatError::Parse(_) => assert!(!path.exists()),
+                _ => panic!("Expected FormatError::Parse, got Err({:?})", e),
+            },
+        }
+    }
+
+    #[test]
+    fn test_conversions() {
+        let input = "<a><b>x</b></a>";
+
+        let doc: RXML = input.into();
+        assert_eq!(doc.parse().unwrap().text_content(), "x");
+
+        let doc = RXML::from(String::from(input));
+        assert_eq!(doc.parse().unwrap().find_all("b").len(), 1);
+
+        let doc: RXML = input.parse().unwrap();
+        assert_eq!(doc.parse().unwrap().tag.name, "a");
+
+        // Conversions are lazy, malformed input only fails once parsed.
+        let doc: RXML = "<a>".parse().unwrap();
+        assert!(doc.parse().is_err());
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let input: &[u8] = b"\xEF\xBB\xBF<list><item>1</item><item>2</item></list>";
+
+        let root = RXML::from_reader(io::Cursor::new(input))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(root.find_all("item").len(), 2);
+
+        let root = RXML::from_reader(&b"<a>x</a>"[..])
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(root.text_content(), "x");
+
+        let invalid: &[u8] = b"<a>\xFF</a>";
+        let err = RXML::from_reader(invalid).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_parse_file() {
+        let path = std::env::temp_dir().join(format!("rxml-parse-file-{}.xml", std::process::id()));
+
+        fs::write(&path, "\u{FEFF}<notes><note id='1'>José</note></notes>").unwrap();
+        let root = RXML::parse_file(&path).unwrap();
+        assert_eq!(root.tag.name, "notes");
+        assert_eq!(root.find_first("note").unwrap().text_content(), "José");
+
+        fs::write(&path, "<notes><note></notes>").unwrap();
+        let parsed = RXML::parse_file(&path);
+        fs::remove_file(&path).unwrap();
+        match parsed {
+            Ok(node) => panic!("Expected FormatError::Parse, got node: {:?}", node),
+            Err(e) => match e {
+                FormatError::Parse(ParseError::UnexpectedClosingTag { .. }) => {}
+                _ => panic!("Expected FormatError::Parse, got Err({:?})", e),
+            },
+        }
+
+        match RXML::parse_file(&path) {
+            Ok(node) => panic!("Expected FormatError::Io, got node: {:?}", node),
+            Err(e) => match e {
+                FormatError::Io(io_error) => assert_eq!(io_error.kind(), io::ErrorKind::NotFound),
+                _ => panic!("Expected FormatError::Io, got Err({:?})", e),
+            },
+        }
+    }
+
+    #[test]
+    fn test_validate_well_formed() {
+        assert!(validate_well_formed("<a x='1'><b>text</b></a>").is_ok());
+
+        match validate_well_formed("<a><b></a>") {
+            Ok(_) => panic!("Expected UnexpectedClosingTag, got Ok"),
+            Err(e) => match e {
+                ParseError::UnexpectedClosingTag { .. } => {}
+                _ => panic!("Expected UnexpectedClosingTag, got Err({:?})", e),
+            },
+        }
+    }
+
+    #[test]
+    fn test_semantically_equal_different_formatting() {
+        let a = "<person name='John' age='55'><city>  Paris </city><zip>75</zip></person>";
+        let b = "<person  age=\"55\"   name=\"John\">\n  <city>Paris</city>\n  <zip> 75 </zip>\n</person>";
+
+        assert!(semantically_equal(a, b).unwrap());
+    }
+
+    #[test]
+    fn test_semantically_equal_element_order_matters() {
+        let a = "<person><city>Paris</city><zip>75</zip></person>";
+        let b = "<person><zip>75</zip><city>Paris</city></person>";
+
+        assert!(!semantically_equal(a, b).unwrap());
+    }
+
+    #[test]
+    fn test_semantically_equal_text_and_attributes_matter() {
+        let a = "<person name='John'><city>Paris</city></person>";
+
+        assert!(!semantically_equal(a, "<person name='John'><city>Lyon</city></person>").unwrap());
+        assert!(!semantically_equal(a, "<person name='Jane'><city>Paris</city></person>").unwrap());
+    }
+
+    #[test]
+    fn test_semantically_equal_propagates_parse_errors() {
+        match semantically_equal("<a></a>", "<a") {
+            Ok(v) => panic!("Expected UnterminatedAngularBracket, got Ok({})", v),
+            Err(e) => match e {
+                ParseError::UnterminatedAngularBracket(_) => {}
+                _ => panic!("Expected UnterminatedAngularBracket, got Err({:?})", e),
+            },
+        }
     }
 }
@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+
+/// The URI the `xml` prefix is bound to without being declared.
+pub const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// The namespace declarations in scope at a node, from `xmlns` and `xmlns:*`
+/// attributes on the node and its ancestors, the nearest declaration of a
+/// prefix hiding those further up. Built by `XMLNode::namespace_context`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct NamespaceContext {
+    /// Declared URIs by prefix, `None` standing for the default namespace. An
+    /// empty URI is kept as it undeclares the binding further up.
+    bindings: HashMap<Option<String>, String>,
+}
+
+impl NamespaceContext {
+    /// Records the declarations among `attributes` that are not already
+    /// bound, which is how a nearer declaration takes precedence when the
+    /// attributes are added nearest first.
+    pub(crate) fn declare_missing(&mut self, attributes: &HashMap<String, String>) {
+        for (name, uri) in attributes {
+            let prefix = match name.strip_prefix("xmlns") {
+                Some("") => None,
+                Some(rest) => match rest.strip_prefix(':') {
+                    Some(prefix) => Some(String::from(prefix)),
+                    None => continue,
+                },
+                None => continue,
+            };
+            self.bindings.entry(prefix).or_insert_with(|| uri.clone());
+        }
+    }
+    /// The URI `prefix` is bound to, `None` standing for the default
+    /// namespace. The `xml` prefix is always bound, and an empty `xmlns=""`
+    /// leaves the default namespace unbound.
+    pub fn resolve(&self, prefix: Option<&str>) -> Option<&str> {
+        if prefix == Some("xml") {
+            return Some(XML_NAMESPACE);
+        }
+        self.bindings
+            .get(&prefix.map(String::from))
+            .map(String::as_str)
+            .filter(|uri| !uri.is_empty())
+    }
+    /// The bound prefixes with their URIs in no particular order, `None`
+    /// standing for the default namespace. The implicit `xml` prefix is left
+    /// out unless declared.
+    pub fn bindings(&self) -> impl Iterator<Item = (Option<&str>, &str)> {
+        self.bindings
+            .iter()
+            .filter(|(_, uri)| !uri.is_empty())
+            .map(|(prefix, uri)| (prefix.as_deref(), uri.as_str()))
+    }
+}
@@ -0,0 +1,116 @@
+use std::fmt;
+
+/// How an `&` that does not start a recognized entity or character reference
+/// is treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EntityPolicy {
+    /// Keep it as written, so `Tom & Jerry` parses as is.
+    #[default]
+    Lenient,
+    /// Fail with `ParseError::UnrecognizedEntity`.
+    Strict,
+}
+
+/// How the whitespace in an element's text is treated once the element is
+/// closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextMode {
+    /// Keep the text exactly as written.
+    #[default]
+    Preserve,
+    /// Strip whitespace from the start and end of the element's text.
+    Trim,
+    /// Trim, and replace every run of whitespace inside the text with a
+    /// single space.
+    Collapse,
+}
+
+/// What `XMLNode::equals_ignoring` disregards. Tag names, attribute names
+/// and values, text and children are always compared, each toggle relaxes one
+/// of those comparisons. The order attributes were written in never matters
+/// as they are kept in a map, and comments are not part of the tree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompareOptions {
+    /// Trim each node's text and collapse whitespace runs inside it, so
+    /// `" a   b "` equals `"a b"`.
+    pub whitespace: bool,
+    /// Compare text case-insensitively. Names and attributes stay
+    /// case-sensitive.
+    pub text_case: bool,
+    /// Match children regardless of their order, each child pairing with a
+    /// distinct equal child of the other node.
+    pub element_order: bool,
+}
+
+/// Default for `ParseOptions::max_depth`.
+pub const DEFAULT_MAX_DEPTH: usize = 1024;
+
+/// Maps an element name to the one stored in the tree.
+pub type TagNameTransform = Box<dyn Fn(&str) -> String>;
+
+/// Knobs controlling how a document is parsed, `ParseOptions::default()`
+/// matches `RXML::new`.
+pub struct ParseOptions {
+    pub entity_policy: EntityPolicy,
+    /// Applied to every element name as it is parsed, opening and closing
+    /// tags alike, e.g. `str::to_lowercase` to accept `<DIV></div>`.
+    pub tag_name_transform: Option<TagNameTransform>,
+    /// Keep every value of an attribute written more than once, readable
+    /// through `XMLNode::get_all_attributes`. Otherwise the last one wins.
+    pub attribute_multimap: bool,
+    /// When set, relative references in the attributes named by
+    /// `uri_attributes` are resolved against it and stored in absolute form.
+    pub base_uri: Option<String>,
+    /// Attributes holding URI references, `href` and `src` by default.
+    pub uri_attributes: Vec<String>,
+    /// Accept HTML style attributes, `<input disabled>` and `<td colspan=2>`.
+    /// A bare name gets an empty value. Otherwise bare names are skipped and
+    /// unquoted values are an error.
+    pub lenient_attributes: bool,
+    /// Applied to the text of every element. `XMLParser::stats` counts text
+    /// as written regardless.
+    pub text_mode: TextMode,
+    /// Let a closing tag close an element whose name differs only in ASCII
+    /// case, as in `<DIV></div>`. Names are stored as written, unlike with a
+    /// lowercasing `tag_name_transform`.
+    pub case_insensitive_tags: bool,
+    /// Deepest nesting of elements allowed, the root being at depth 1.
+    /// Deeper documents fail with `ParseError::MaxDepthExceeded`, which keeps
+    /// untrusted input from growing the stack of open elements without bound.
+    pub max_depth: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            entity_policy: EntityPolicy::default(),
+            tag_name_transform: None,
+            attribute_multimap: false,
+            base_uri: None,
+            uri_attributes: vec![String::from("href"), String::from("src")],
+            lenient_attributes: false,
+            text_mode: TextMode::default(),
+            case_insensitive_tags: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+impl fmt::Debug for ParseOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParseOptions")
+            .field("entity_policy", &self.entity_policy)
+            .field(
+                "tag_name_transform",
+                &self.tag_name_transform.as_ref().map(|_| ".."),
+            )
+            .field("attribute_multimap", &self.attribute_multimap)
+            .field("base_uri", &self.base_uri)
+            .field("uri_attributes", &self.uri_attributes)
+            .field("lenient_attributes", &self.lenient_attributes)
+            .field("text_mode", &self.text_mode)
+            .field("case_insensitive_tags", &self.case_insensitive_tags)
+            .field("max_depth", &self.max_depth)
+            .finish()
+    }
+}
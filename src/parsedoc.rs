@@ -1,29 +1,50 @@
 use crate::{
     api::{XMLNode, XMLTag},
-    error,
-    parsetag::{BaseXMLTag, TagKind, TagParser},
+    declaration::XmlDeclaration,
+    entities, error,
+    event::Event,
+    options::{EntityPolicy, ParseOptions, TextMode, DEFAULT_MAX_DEPTH},
+    parsetag::{self, BaseXMLTag, TagKind, TagParser, TagToken},
+    stats::DocumentStats,
+    token, uri,
 };
+use std::borrow::Cow;
 use std::cell::RefCell;
 use std::cmp::PartialEq;
+use std::collections::HashMap;
 use std::mem::discriminant;
 use std::rc::Rc;
 
 #[derive(Debug)]
-enum TokenKind {
+enum TokenKind<'a> {
     Tag(BaseXMLTag),
     String,
     EndOfFile,
     Whitespace,
+    /// The body of a comment, without the `<!--` and `-->` delimiters. The
+    /// parser skips comments for now, the body is kept for an option that
+    /// retains them.
+    #[allow(dead_code)]
+    Comment(&'a str),
+    /// `<!DOCTYPE ...>`, internal subset included. Only allowed in the prolog.
+    Doctype,
+    /// The verbatim content of a CDATA section, without `<![CDATA[` and `]]>`.
+    CData(&'a str),
+    /// `<?target body?>`, the XML declaration having target `xml`.
+    ProcessingInstruction {
+        target: &'a str,
+        body: &'a str,
+    },
 }
 
 #[derive(Debug)]
 struct DocToken<'a> {
     text: &'a str,
-    kind: TokenKind,
+    kind: TokenKind<'a>,
     position: usize,
 }
 impl<'a> DocToken<'a> {
-    fn new(text: &'a str, kind: TokenKind, position: usize) -> Self {
+    fn new(text: &'a str, kind: TokenKind<'a>, position: usize) -> Self {
         Self {
             text,
             kind,
@@ -50,22 +71,29 @@ impl<'a> PartialEq for DocToken<'a> {
 pub struct XMLLexer<'a> {
     content: &'a str,
     position: RefCell<usize>,
+    lenient_attributes: bool,
+    /// The buffer every tag is lexed into, kept empty between tags so only
+    /// its allocation carries over, see `parsetag::recycle`.
+    tag_tokens: RefCell<Vec<TagToken<'static>>>,
 }
 impl<'a> XMLLexer<'a> {
     fn new(content: &'a str) -> Self {
         Self {
             content,
             position: RefCell::new(0),
+            lenient_attributes: false,
+            tag_tokens: RefCell::default(),
         }
     }
     fn current(&self) -> char {
         if self.end() {
             return '\0';
         }
-        self.content.as_bytes()[self.cur()] as char
+        self.content[self.cur()..].chars().next().unwrap_or('\0')
     }
+    /// Steps over the current character, however many bytes it takes.
     fn next(&self) {
-        *self.position.borrow_mut() += 1;
+        *self.position.borrow_mut() += self.current().len_utf8();
     }
     fn cur(&self) -> usize {
         *self.position.borrow()
@@ -73,131 +101,877 @@ impl<'a> XMLLexer<'a> {
     fn end(&self) -> bool {
         self.cur() >= self.content.len()
     }
-    fn next_token(&self) -> Result<DocToken, error::ParseError> {
+    /// Moves past the tag starting at the current `<`, returning its text up
+    /// to and including the closing `>`. Only quotes are looked at, the tag
+    /// itself is not parsed.
+    fn scan_tag(&self) -> Result<&'a str, error::ParseError> {
+        let start = self.cur();
+        self.next();
+
+        // A `>` inside a quoted attribute value does not close the tag.
+        let mut quote = None;
+        while quote.is_some() || self.current() != '>' {
+            if self.end() {
+                return Err(error::ParseError::UnterminatedAngularBracket(start));
+            }
+            match (quote, self.current()) {
+                (None, c @ ('"' | '\'')) => quote = Some(c),
+                (Some(open), c) if c == open => quote = None,
+                _ => {}
+            }
+            self.next();
+        }
+        self.next();
+        Ok(&self.content[start..self.cur()])
+    }
+    fn next_token(&self) -> Result<DocToken<'a>, error::ParseError> {
         let start = self.cur();
         if self.end() {
-            return Ok(DocToken::new(
-                &self.content[self.content.len() - 1..self.content.len() - 1],
+            Ok(DocToken::new(
+                &self.content[self.content.len()..],
                 TokenKind::EndOfFile,
                 self.content.len(),
-            ));
+            ))
         } else if self.current().is_whitespace() {
             self.next();
-            return Ok(DocToken::new(
+            Ok(DocToken::new(
                 &self.content[start..self.cur()],
                 TokenKind::Whitespace,
                 start,
-            ));
-        } else if self.current() == '<' {
-            self.next();
-
-            while self.current() != '>' {
-                if self.end() {
-                    return Err(error::ParseError::UnterminatedAngularBracket(start));
-                }
-                self.next();
+            ))
+        } else if self.content[start..].starts_with("<!--") {
+            let body_start = start + 4;
+            let body_end = match self.content[body_start..].find("-->") {
+                Some(found) => body_start + found,
+                None => return Err(error::ParseError::UnterminatedComment(start)),
+            };
+            *self.position.borrow_mut() = body_end + 3;
+            Ok(DocToken::new(
+                &self.content[start..self.cur()],
+                TokenKind::Comment(&self.content[body_start..body_end]),
+                start,
+            ))
+        } else if self.content[start..].starts_with("<![CDATA[") {
+            let body_start = start + 9;
+            let body_end = match self.content[body_start..].find("]]>") {
+                Some(found) => body_start + found,
+                None => return Err(error::ParseError::UnterminatedCData(start)),
+            };
+            *self.position.borrow_mut() = body_end + 3;
+            Ok(DocToken::new(
+                &self.content[start..self.cur()],
+                TokenKind::CData(&self.content[body_start..body_end]),
+                start,
+            ))
+        } else if starts_with_doctype(&self.content[start..]) {
+            let end = match doctype_end(&self.content[start..]) {
+                Some(length) => start + length,
+                None => return Err(error::ParseError::UnterminatedAngularBracket(start)),
+            };
+            *self.position.borrow_mut() = end;
+            Ok(DocToken::new(
+                &self.content[start..end],
+                TokenKind::Doctype,
+                start,
+            ))
+        } else if self.content[start..].starts_with("<?") {
+            let end = match self.content[start + 2..].find("?>") {
+                Some(found) => start + 2 + found + 2,
+                None => return Err(error::ParseError::MalformedProcessingInstruction(start)),
+            };
+            let text = &self.content[start..end];
+            if !is_processing_instruction(text) {
+                return Err(error::ParseError::MalformedProcessingInstruction(start));
             }
+            *self.position.borrow_mut() = end;
 
-            let tagtext = &self.content[start..self.cur() + 1];
+            let inner = &text[2..text.len() - 2];
+            let target_end = inner.find(char::is_whitespace).unwrap_or(inner.len());
+            Ok(DocToken::new(
+                text,
+                TokenKind::ProcessingInstruction {
+                    target: &inner[..target_end],
+                    body: &inner[target_end..],
+                },
+                start,
+            ))
+        } else if self.current() == '<' {
+            let tagtext = self.scan_tag()?;
 
-            self.next();
+            if tagtext.starts_with("<!") {
+                return Err(error::ParseError::MalformedComment(start));
+            }
 
-            let tagparser = TagParser::new(tagtext, start);
+            let tokens = parsetag::recycle(self.tag_tokens.take());
+            let tagparser = TagParser::new(tagtext, start)
+                .reusing(tokens)
+                .lenient(self.lenient_attributes);
+            let tag = tagparser.parse();
+            self.tag_tokens
+                .replace(parsetag::recycle(tagparser.into_tokens()));
 
-            let tag = match tagparser.parse() {
+            let tag = match tag {
                 Ok(t) => t,
                 Err(e) => {
                     return Err(error::ParseError::TagParseError(e));
                 }
             };
 
-            return Ok(DocToken::new(tagtext, TokenKind::Tag(tag), start));
+            Ok(DocToken::new(tagtext, TokenKind::Tag(tag), start))
         } else {
-            while !self.current().is_whitespace() || self.end() {
-                if self.current() == '<' {
-                    break;
-                }
+            // Whitespace inside the run belongs to the text, only leading
+            // whitespace is lexed on its own.
+            while !self.end() && self.current() != '<' {
                 self.next();
             }
-            return Ok(DocToken::new(
+            if self.cur() == start {
+                // Never hand out empty text, it would become a spurious empty run.
+                return self.next_token();
+            }
+            Ok(DocToken::new(
                 &self.content[start..self.cur()],
                 TokenKind::String,
                 start,
-            ));
+            ))
         }
     }
 }
 
+/// `<!DOCTYPE`, in any case as HTML writes it `<!doctype html>`.
+fn starts_with_doctype(text: &str) -> bool {
+    text.get(..9)
+        .is_some_and(|start| start.eq_ignore_ascii_case("<!DOCTYPE"))
+}
+
+/// Length of the doctype declaration `text` starts with, up to and including
+/// the `>` closing it. A `>` inside the bracketed internal subset, a quoted
+/// literal or a comment does not count.
+fn doctype_end(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut index = 0;
+    while let Some(c) = text[index..].chars().next() {
+        match c {
+            '"' | '\'' => index += 1 + text[index + 1..].find(c)?,
+            '<' if text[index..].starts_with("<!--") => {
+                index += 4 + text[index + 4..].find("-->")? + 2;
+            }
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            '>' if depth <= 0 => return Some(index + 1),
+            _ => {}
+        }
+        index += c.len_utf8();
+    }
+    None
+}
+
+/// `<?target ...?>` where the target directly follows the `<?`.
+fn is_processing_instruction(text: &str) -> bool {
+    let starts_with_target = text[2..]
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_' || c == ':');
+    text.len() >= 5 && text.ends_with("?>") && starts_with_target
+}
+
+/// The element content is currently added to. An empty stack means the root
+/// has already closed and nothing but whitespace, comments and processing
+/// instructions may follow.
+fn innermost(
+    node_stack: &[Rc<XMLNode>],
+    position: usize,
+) -> Result<&Rc<XMLNode>, error::ParseError> {
+    node_stack
+        .last()
+        .ok_or(error::ParseError::ContentOutsideRoot { position })
+}
+
+/// Processing instructions are skipped, but the XML declaration may only
+/// appear at the very start of the document.
+fn check_declaration_position(target: &str, position: usize) -> Result<(), error::ParseError> {
+    match target == "xml" && position != 0 {
+        true => Err(error::ParseError::MisplacedDeclaration(position)),
+        false => Ok(()),
+    }
+}
+
 pub struct XMLParser<'a> {
     lexer: XMLLexer<'a>,
+    entity_policy: EntityPolicy,
+    tag_name_transform: Option<&'a dyn Fn(&str) -> String>,
+    attribute_multimap: bool,
+    base_uri: Option<&'a str>,
+    uri_attributes: &'a [String],
+    text_mode: TextMode,
+    case_insensitive_tags: bool,
+    max_depth: usize,
+    /// Where errors are collected while recovering from them, see
+    /// `parse_all`.
+    errors: RefCell<Option<Vec<error::ParseError>>>,
+    /// The entities declared in the internal subset of the doctype, see
+    /// `entities::internal_entities`.
+    entities: RefCell<HashMap<String, String>>,
 }
 
 impl<'a> XMLParser<'a> {
     pub fn new(content: &'a str) -> Self {
         Self {
             lexer: XMLLexer::new(content),
+            entity_policy: EntityPolicy::default(),
+            tag_name_transform: None,
+            attribute_multimap: false,
+            base_uri: None,
+            uri_attributes: &[],
+            text_mode: TextMode::default(),
+            case_insensitive_tags: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            errors: RefCell::new(None),
+            entities: RefCell::default(),
+        }
+    }
+    pub fn with_options(content: &'a str, options: &'a ParseOptions) -> Self {
+        Self {
+            lexer: XMLLexer {
+                lenient_attributes: options.lenient_attributes,
+                ..XMLLexer::new(content)
+            },
+            entity_policy: options.entity_policy,
+            tag_name_transform: options.tag_name_transform.as_deref(),
+            attribute_multimap: options.attribute_multimap,
+            base_uri: options.base_uri.as_deref(),
+            uri_attributes: &options.uri_attributes,
+            text_mode: options.text_mode,
+            case_insensitive_tags: options.case_insensitive_tags,
+            max_depth: options.max_depth,
+            errors: RefCell::new(None),
+            entities: RefCell::default(),
+        }
+    }
+    /// Lexes tags into `tag_tokens` rather than a new buffer, see `Parser`.
+    pub(crate) fn reusing(self, tag_tokens: Vec<TagToken<'static>>) -> Self {
+        self.lexer.tag_tokens.replace(tag_tokens);
+        self
+    }
+    /// Hands back the tag token buffer for the next document.
+    pub(crate) fn take_tag_tokens(&self) -> Vec<TagToken<'static>> {
+        self.lexer.tag_tokens.take()
+    }
+    /// Pulls the next token off the lexer, applying the tag name transform to
+    /// opening and closing tags alike so they keep balancing.
+    fn next_token(&self) -> Result<DocToken<'a>, error::ParseError> {
+        let mut token = self.lexer.next_token()?;
+        if let (TokenKind::Tag(tag), Some(transform)) = (&mut token.kind, self.tag_name_transform) {
+            tag.name = transform(&tag.name);
         }
+        Ok(token)
     }
     pub fn parse(&'a self) -> Result<Rc<XMLNode>, error::ParseError> {
         let mut node_stack: Vec<Rc<XMLNode>> = Vec::new();
+        self.parse_with_stack(&mut node_stack)
+    }
+    /// Same as `parse` but uses the caller's `node_stack` as scratch space so
+    /// its allocation can be reused across documents. The stack is cleared
+    /// before use.
+    pub fn parse_with_stack(
+        &'a self,
+        node_stack: &mut Vec<Rc<XMLNode>>,
+    ) -> Result<Rc<XMLNode>, error::ParseError> {
+        node_stack.clear();
+        let root = self.open_root(node_stack)?;
+        self.build(node_stack, 0)?;
+        Ok(root)
+    }
+    /// Parses like `parse` but carries on past the errors it can recover
+    /// from, returning every error found along with as much of the tree as
+    /// could be built. Recovered from are:
+    ///
+    /// - malformed tags and comments, which are skipped,
+    /// - bad entity and character references, kept as written,
+    /// - closing tags that match no open element, which are dropped, or one
+    ///   further out, closing everything up to it,
+    /// - elements and text after the root, and misplaced XML and DOCTYPE
+    ///   declarations, all skipped,
+    /// - elements left open at the end, the tree is returned as is.
+    ///
+    /// Anything else ends parsing: errors in the prolog or the root tag,
+    /// which leave no tree, and constructs whose end cannot be told such as
+    /// an unterminated tag, comment or CDATA section.
+    pub fn parse_all(&'a self) -> (Option<Rc<XMLNode>>, Vec<error::ParseError>) {
+        let mut node_stack: Vec<Rc<XMLNode>> = Vec::new();
+        *self.errors.borrow_mut() = Some(Vec::new());
+        let root = self.open_root(&mut node_stack);
+        let last = match &root {
+            Ok(_) => self.build(&mut node_stack, 0).err(),
+            Err(_) => None,
+        };
+        let mut errors = self.errors.take().unwrap_or_default();
+        match root {
+            Ok(root) => {
+                errors.extend(last);
+                (Some(root), errors)
+            }
+            Err(e) => {
+                errors.push(e);
+                (None, errors)
+            }
+        }
+    }
+    /// Reads the prolog and the root's opening tag, leaving the root on
+    /// `node_stack` unless it is self-closing.
+    fn open_root(
+        &self,
+        node_stack: &mut Vec<Rc<XMLNode>>,
+    ) -> Result<Rc<XMLNode>, error::ParseError> {
+        let first_token = self.next_after_prolog()?;
+        let first_end = first_token.position + first_token.text.len();
+        match &first_token.kind {
+            TokenKind::Tag(tag) if tag.kind != TagKind::Closing => {
+                self.check_depth(1, first_token.position)?
+            }
+            _ => {}
+        }
 
-        let first_tag = match self.lexer.next_token()?.kind {
-            TokenKind::Tag(tag) => XMLTag::from(tag),
-            _ => {
-                return Err(error::ParseError::InvalidFirstToken);
+        match first_token.kind {
+            TokenKind::Tag(tag) if tag.kind == TagKind::Closing => {
+                Err(error::ParseError::ClosingTagNeverOpened {
+                    obtained: tag.name,
+                    position: tag.pos,
+                })
             }
-        };
+            TokenKind::Tag(tag) if tag.kind == TagKind::SelfClosing => {
+                let root = self.create_node(tag)?;
+                root.set_span((first_token.position, first_end));
+                Ok(root)
+            }
+            TokenKind::Tag(tag) => {
+                let root = self.create_node(tag)?;
+                node_stack.push(Rc::clone(&root));
+                Ok(root)
+            }
+            TokenKind::EndOfFile => Err(error::ParseError::NoTokensToParse(first_token.position)),
+            _ => Err(error::ParseError::InvalidFirstToken),
+        }
+    }
+    /// Skips the prolog: the XML declaration, processing instructions,
+    /// comments and whitespace ahead of the root. Returns the first token
+    /// after it.
+    fn next_after_prolog(&self) -> Result<DocToken<'a>, error::ParseError> {
+        loop {
+            let token = self.next_token()?;
+            match token.kind {
+                TokenKind::ProcessingInstruction { target, .. } => {
+                    check_declaration_position(target, token.position)?
+                }
+                TokenKind::Doctype => {
+                    *self.entities.borrow_mut() = entities::internal_entities(token.text)
+                }
+                TokenKind::Comment(_) | TokenKind::Whitespace => {}
+                _ => return Ok(token),
+            }
+        }
+    }
+    /// The XML declaration the content starts with, if any.
+    pub fn declaration(&self) -> Option<XmlDeclaration> {
+        match self.lexer.next_token().ok()?.kind {
+            TokenKind::ProcessingInstruction {
+                target: "xml",
+                body,
+            } => XmlDeclaration::parse(body),
+            _ => None,
+        }
+    }
+    /// Parses the content as a fragment, appending every top level element and
+    /// text run to `parent` instead of creating a new root. The fragment is
+    /// built apart and only attached once it parsed, so `parent` is left
+    /// untouched on error.
+    pub fn parse_into(&'a self, parent: &Rc<XMLNode>) -> Result<(), error::ParseError> {
+        let scratch = Rc::new(XMLNode::new(parent.tag.clone()));
+        let mut node_stack: Vec<Rc<XMLNode>> = vec![Rc::clone(&scratch)];
+        self.build(&mut node_stack, 1)?;
 
-        let first_node = Rc::new(XMLNode::new(first_tag));
+        let runs = scratch.text_runs();
+        for (run, child) in runs.iter().zip(scratch.cloned_children()) {
+            parent.push_content(run);
+            child.set_parent(parent);
+            parent.append_child(child);
+        }
+        parent.push_content(&runs[runs.len() - 1]);
+        Ok(())
+    }
+    /// Turns an opening tag into a node, decoding its attribute values and
+    /// resolving URI references against the base URI.
+    fn create_node(&self, mut tag: BaseXMLTag) -> Result<Rc<XMLNode>, error::ParseError> {
+        self.decode_attributes(&mut tag)?;
 
-        node_stack.push(Rc::clone(&first_node));
+        let mut repeated: HashMap<String, Vec<String>> = HashMap::new();
+        if self.attribute_multimap {
+            for (name, value, position) in tag.repeated.drain(..) {
+                let decoded = match self.attribute_value(&name, &value, position) {
+                    Ok(decoded) => decoded.into_owned(),
+                    Err(e) => {
+                        self.recover(e)?;
+                        value
+                    }
+                };
+                repeated.entry(name).or_default().push(decoded);
+            }
+            for (name, values) in repeated.iter_mut() {
+                values.push(tag.attribs[name].clone());
+            }
+        }
 
+        let tag = XMLTag::from(tag);
+        *tag.repeated_attributes.borrow_mut() = repeated;
+        Ok(Rc::new(XMLNode::new(tag)))
+    }
+    /// Fails once an element at `depth`, 1 being the root, nests deeper than
+    /// allowed.
+    fn check_depth(&self, depth: usize, position: usize) -> Result<(), error::ParseError> {
+        if depth > self.max_depth {
+            return Err(error::ParseError::MaxDepthExceeded { depth, position });
+        }
+        Ok(())
+    }
+    /// Whether closing tag `closing` closes element `open`.
+    fn names_match(&self, open: &str, closing: &str) -> bool {
+        match self.case_insensitive_tags {
+            true => open.eq_ignore_ascii_case(closing),
+            false => open == closing,
+        }
+    }
+    fn decode_attributes(&self, tag: &mut BaseXMLTag) -> Result<(), error::ParseError> {
+        for (name, value) in tag.attribs.iter_mut() {
+            let position = tag.value_positions.get(name).copied().unwrap_or(tag.pos);
+            match self.attribute_value(name, value, position) {
+                Ok(Cow::Owned(decoded)) => *value = decoded,
+                Ok(Cow::Borrowed(_)) => {}
+                Err(e) => self.recover(e)?,
+            }
+        }
+        Ok(())
+    }
+    /// Replaces the references in `text`, including the entities the doctype
+    /// declared, under the parser's entity policy.
+    fn decode<'t>(
+        &self,
+        text: &'t str,
+        position: usize,
+    ) -> Result<Cow<'t, str>, error::ParseError> {
+        let defined = self.entities.borrow();
+        entities::decode_with(text, position, self.entity_policy, &defined)
+    }
+    fn attribute_value<'v>(
+        &self,
+        name: &str,
+        value: &'v str,
+        position: usize,
+    ) -> Result<Cow<'v, str>, error::ParseError> {
+        let decoded = self.decode(value, position)?;
+        match self.base_uri {
+            Some(base) if self.uri_attributes.iter().any(|n| n == name) => {
+                Ok(Cow::Owned(uri::resolve(base, &decoded)))
+            }
+            _ => Ok(decoded),
+        }
+    }
+    /// Reads the document as a stream of events without building a tree.
+    /// Only the names of the open elements are kept, enough to check that
+    /// every closing tag matches and nothing is left open, see `Events`.
+    pub fn events(self) -> Events<'a> {
+        Events {
+            parser: self,
+            open: Vec::new(),
+            root_closed: false,
+            pending: None,
+            pending_end: None,
+            started: false,
+            done: false,
+        }
+    }
+    /// Reads the document as tokens with their byte ranges, see `Tokens`.
+    pub fn tokens(self) -> Tokens<'a> {
+        Tokens {
+            lexer: self.lexer,
+            done: false,
+        }
+    }
+    /// Collects `DocumentStats` in a single pass over `events`, without
+    /// building a tree, so the document is checked just as thoroughly.
+    pub fn stats(self) -> Result<DocumentStats, error::ParseError> {
+        let mut stats = DocumentStats::default();
+        let mut depth = 0;
+        for event in self.events() {
+            match event? {
+                Event::StartElement { attributes, .. } => {
+                    depth += 1;
+                    stats.element_count += 1;
+                    stats.attribute_count += attributes.len();
+                    stats.max_depth = stats.max_depth.max(depth);
+                }
+                Event::EndElement { .. } => depth -= 1,
+                Event::Text(text) => stats.text_length += text.len(),
+                Event::Eof => {}
+            }
+        }
+        Ok(stats)
+    }
+    /// Consumes the remaining tokens, attaching nodes to the top of
+    /// `node_stack`. Closing tags never pop the stack below `floor` nodes.
+    fn build(
+        &'a self,
+        node_stack: &mut Vec<Rc<XMLNode>>,
+        floor: usize,
+    ) -> Result<(), error::ParseError> {
         while !self.lexer.end() {
-            let cur_token = self.lexer.next_token()?;
-
-            match cur_token.kind {
-                TokenKind::Tag(tag) => match tag.kind {
-                    TagKind::Opening => {
-                        let _new_node = Rc::new(XMLNode::new(XMLTag::from(tag)));
-                        let new_node = Rc::clone(&_new_node);
-                        node_stack
-                            .last()
-                            .unwrap()
-                            .children
-                            .borrow_mut()
-                            .push(Rc::clone(&new_node));
-                        node_stack.push(Rc::clone(&new_node));
+            let cur_token = match self.next_token() {
+                Ok(token) => token,
+                // The lexer has already stepped over the malformed tag.
+                Err(
+                    e @ (error::ParseError::TagParseError(_)
+                    | error::ParseError::MalformedComment(_)),
+                ) => {
+                    self.recover(e)?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if let TokenKind::EndOfFile = cur_token.kind {
+                break;
+            }
+            match self.add_token(node_stack, floor, cur_token) {
+                Ok(()) => {}
+                // Going on would only nest deeper still.
+                Err(e @ error::ParseError::MaxDepthExceeded { .. }) => return Err(e),
+                Err(e) => self.recover(e)?,
+            }
+        }
+        if node_stack.len() > floor {
+            let unclosed = &node_stack[floor..];
+            return Err(error::ParseError::UnclosedTags {
+                names: unclosed.iter().map(|node| node.tag.name.clone()).collect(),
+                positions: unclosed.iter().map(|node| node.tag._pos).collect(),
+            });
+        }
+        Ok(())
+    }
+    /// Adds what `token` stands for to the tree being built on `node_stack`.
+    fn add_token(
+        &self,
+        node_stack: &mut Vec<Rc<XMLNode>>,
+        floor: usize,
+        token: DocToken<'a>,
+    ) -> Result<(), error::ParseError> {
+        let end = token.position + token.text.len();
+
+        match token.kind {
+            TokenKind::Tag(tag) => match tag.kind {
+                TagKind::Opening => {
+                    let parent = innermost(node_stack, tag.pos)?;
+                    self.check_depth(node_stack.len() + 1 - floor, tag.pos)?;
+                    let new_node = self.create_node(tag)?;
+                    new_node.set_parent(parent);
+                    parent.append_child(Rc::clone(&new_node));
+                    node_stack.push(new_node);
+                }
+                TagKind::SelfClosing => {
+                    let parent = innermost(node_stack, tag.pos)?;
+                    self.check_depth(node_stack.len() + 1 - floor, tag.pos)?;
+                    let new_node = self.create_node(tag)?;
+                    new_node.set_span((token.position, end));
+                    new_node.set_parent(parent);
+                    parent.append_child(new_node);
+                }
+                TagKind::Closing => self.close(node_stack, floor, tag, end)?,
+            },
+            TokenKind::String => {
+                let node = innermost(node_stack, token.position)?;
+                let text = self
+                    .decode(token.text, token.position)
+                    .or_else(|e| self.recover(e).map(|_| Cow::Borrowed(token.text)))?;
+                node.push_content(&text);
+            }
+            TokenKind::CData(text) => innermost(node_stack, token.position)?.push_content(text),
+            TokenKind::ProcessingInstruction { target, .. } => {
+                check_declaration_position(target, token.position)?
+            }
+            // Whitespace after the root closed is outside any element.
+            TokenKind::Whitespace => {
+                if let Some(node) = node_stack.last() {
+                    node.push_content(token.text)
+                }
+            }
+            TokenKind::Doctype => {
+                return Err(error::ParseError::MisplacedDoctype(token.position));
+            }
+            TokenKind::Comment(_) | TokenKind::EndOfFile => {}
+        }
+        Ok(())
+    }
+    /// Closes the innermost open element with closing tag `tag`, which ends
+    /// at byte `end`. When recovering from a mismatched closing tag, it
+    /// closes the nearest open element of its name along with everything
+    /// inside it, and is dropped when there is none.
+    fn close(
+        &self,
+        node_stack: &mut Vec<Rc<XMLNode>>,
+        floor: usize,
+        tag: BaseXMLTag,
+        end: usize,
+    ) -> Result<(), error::ParseError> {
+        let top = match node_stack.last() {
+            Some(node) if node_stack.len() > floor => node,
+            _ => {
+                return Err(error::ParseError::ClosingTagNeverOpened {
+                    obtained: tag.name,
+                    position: tag.pos,
+                });
+            }
+        };
+        let mut closing = 1;
+        if !self.names_match(&top.tag.name, &tag.name) {
+            let error = error::ParseError::UnexpectedClosingTag {
+                expected: top.tag.name.to_owned(),
+                obtained: tag.name.to_owned(),
+                position: top.tag._pos,
+            };
+            let open = node_stack[floor..]
+                .iter()
+                .rev()
+                .position(|node| self.names_match(&node.tag.name, &tag.name));
+            self.recover(error)?;
+            closing = match open {
+                Some(depth) => depth + 1,
+                None => return Ok(()),
+            };
+        }
+        for _ in 0..closing {
+            let popped = node_stack.pop().expect("closing only open elements");
+            popped.apply_text_mode(self.text_mode);
+            popped.set_span((popped.tag._pos, end));
+        }
+        Ok(())
+    }
+    /// Records `error` and carries on when collecting every error, see
+    /// `parse_all`, hands it back otherwise.
+    fn recover(&self, error: error::ParseError) -> Result<(), error::ParseError> {
+        match self.errors.borrow_mut().as_mut() {
+            Some(errors) => {
+                errors.push(error);
+                Ok(())
+            }
+            None => Err(error),
+        }
+    }
+}
+/// Pull parser returned by `XMLParser::events`. Adjacent text, whitespace
+/// and CDATA sections are merged into one `Event::Text`; whitespace outside
+/// any element, comments and processing instructions are skipped.
+/// `ParseOptions::text_mode` is not applied. The stream ends with
+/// `Event::Eof` or with the first error.
+pub struct Events<'a> {
+    parser: XMLParser<'a>,
+    open: Vec<(String, usize)>,
+    root_closed: bool,
+    /// A token read past the end of a text run, handled on the next call.
+    pending: Option<DocToken<'a>>,
+    /// End of a self-closing element, due right after its start.
+    pending_end: Option<String>,
+    started: bool,
+    done: bool,
+}
+
+impl<'a> Events<'a> {
+    fn next_token(&mut self) -> Result<DocToken<'a>, error::ParseError> {
+        if let Some(token) = self.pending.take() {
+            return Ok(token);
+        }
+        if !self.started {
+            self.started = true;
+            let token = self.parser.next_after_prolog()?;
+            return match token.kind {
+                TokenKind::Tag(_) => Ok(token),
+                TokenKind::EndOfFile => Err(error::ParseError::NoTokensToParse(token.position)),
+                _ => Err(error::ParseError::InvalidFirstToken),
+            };
+        }
+        self.parser.next_token()
+    }
+    fn next_event(&mut self) -> Result<Event, error::ParseError> {
+        if let Some(name) = self.pending_end.take() {
+            return Ok(Event::EndElement { name });
+        }
+        let mut text = String::new();
+        loop {
+            let token = self.next_token()?;
+            let is_text = matches!(
+                token.kind,
+                TokenKind::String | TokenKind::CData(_) | TokenKind::Whitespace
+            );
+            if !is_text && !text.is_empty() {
+                self.pending = Some(token);
+                return Ok(Event::Text(text));
+            }
+            let outside_root = match &token.kind {
+                TokenKind::Tag(tag) => tag.kind != TagKind::Closing && self.root_closed,
+                TokenKind::String | TokenKind::CData(_) => self.root_closed,
+                _ => false,
+            };
+            if outside_root {
+                return Err(error::ParseError::ContentOutsideRoot {
+                    position: token.position,
+                });
+            }
+            match token.kind {
+                TokenKind::Tag(mut tag) => match tag.kind {
+                    TagKind::Opening | TagKind::SelfClosing => {
+                        self.parser.check_depth(self.open.len() + 1, tag.pos)?;
+                        self.parser.decode_attributes(&mut tag)?;
+                        match tag.kind {
+                            TagKind::Opening => self.open.push((tag.name.clone(), tag.pos)),
+                            _ => self.pending_end = Some(tag.name.clone()),
+                        }
+                        self.root_closed = self.open.is_empty();
+                        return Ok(Event::StartElement {
+                            name: tag.name,
+                            attributes: tag.attribs,
+                        });
                     }
                     TagKind::Closing => {
-                        let popped = match node_stack.pop() {
-                            Some(node) => node,
+                        let (name, pos) = match self.open.pop() {
+                            Some(open) => open,
                             None => {
                                 return Err(error::ParseError::ClosingTagNeverOpened {
-                                    obtained: tag.name.to_owned(),
+                                    obtained: tag.name,
                                     position: tag.pos,
                                 });
                             }
                         };
-
-                        if popped.tag.name != tag.name {
+                        if !self.parser.names_match(&name, &tag.name) {
                             return Err(error::ParseError::UnexpectedClosingTag {
-                                expected: popped.tag.name.to_owned(),
+                                expected: name,
                                 obtained: tag.name,
-                                position: popped.tag._pos,
+                                position: pos,
                             });
                         }
+                        self.root_closed = self.open.is_empty();
+                        return Ok(Event::EndElement { name });
                     }
                 },
-                TokenKind::String => node_stack.last().unwrap().push_content(cur_token.text),
-                TokenKind::Whitespace => {}
+                TokenKind::String => {
+                    text.push_str(&self.parser.decode(token.text, token.position)?)
+                }
+                TokenKind::CData(body) => text.push_str(body),
+                TokenKind::Whitespace if !self.open.is_empty() => text.push_str(token.text),
+                TokenKind::ProcessingInstruction { target, .. } => {
+                    check_declaration_position(target, token.position)?
+                }
+                TokenKind::Doctype => {
+                    return Err(error::ParseError::MisplacedDoctype(token.position));
+                }
+                TokenKind::Whitespace | TokenKind::Comment(_) => {}
                 TokenKind::EndOfFile => {
-                    break;
+                    if !self.open.is_empty() {
+                        let (names, positions) = self.open.drain(..).unzip();
+                        return Err(error::ParseError::UnclosedTags { names, positions });
+                    }
+                    return Ok(Event::Eof);
                 }
             }
         }
-        Ok(first_node)
     }
 }
+
+impl<'a> Iterator for Events<'a> {
+    type Item = Result<Event, error::ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let event = self.next_event();
+        self.done = matches!(event, Ok(Event::Eof) | Err(_));
+        Some(event)
+    }
+}
+
+/// Iterator returned by `XMLParser::tokens`. Whitespace characters in a row
+/// come out as a single token; the stream ends at the end of the input or
+/// after the first error. Element tags are only scanned for their closing
+/// `>`, so a malformed tag such as `<a x=>` still comes out as a token.
+pub struct Tokens<'a> {
+    lexer: XMLLexer<'a>,
+    done: bool,
+}
+
+impl<'a> Tokens<'a> {
+    /// The element tag starting at the current position, told apart by its
+    /// text alone. `None` when the input goes on with something else.
+    fn next_tag(&self) -> Option<Result<(token::TokenKind, usize, usize), error::ParseError>> {
+        let rest = &self.lexer.content[self.lexer.cur()..];
+        if !rest.starts_with('<') || rest.starts_with("<!") || rest.starts_with("<?") {
+            return None;
+        }
+        let start = self.lexer.cur();
+        let text = match self.lexer.scan_tag() {
+            Ok(text) => text,
+            Err(e) => return Some(Err(e)),
+        };
+        let inner = text[1..text.len() - 1].trim();
+        let kind = if inner.starts_with('/') {
+            token::TokenKind::EndTag
+        } else if inner.ends_with('/') {
+            token::TokenKind::EmptyTag
+        } else {
+            token::TokenKind::StartTag
+        };
+        Some(Ok((kind, start, self.lexer.cur())))
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Result<(token::TokenKind, usize, usize), error::ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Some(tag) = self.next_tag() {
+            self.done = tag.is_err();
+            return Some(tag);
+        }
+        let token = match self.lexer.next_token() {
+            Ok(token) => token,
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+        };
+        let kind = match token.kind {
+            TokenKind::EndOfFile => {
+                self.done = true;
+                return None;
+            }
+            TokenKind::Tag(tag) => match tag.kind {
+                TagKind::Opening => token::TokenKind::StartTag,
+                TagKind::Closing => token::TokenKind::EndTag,
+                TagKind::SelfClosing => token::TokenKind::EmptyTag,
+            },
+            TokenKind::String => token::TokenKind::Text,
+            TokenKind::Whitespace => {
+                while !self.lexer.end() && self.lexer.current().is_whitespace() {
+                    self.lexer.next();
+                }
+                token::TokenKind::Whitespace
+            }
+            TokenKind::Comment(_) => token::TokenKind::Comment,
+            TokenKind::Doctype => token::TokenKind::Doctype,
+            TokenKind::CData(_) => token::TokenKind::CData,
+            TokenKind::ProcessingInstruction { .. } => token::TokenKind::ProcessingInstruction,
+        };
+        Some(Ok((kind, token.position, self.lexer.cur())))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
@@ -267,6 +1041,720 @@ mod tests {
         assert_eq!(obtained_tokens, actual_tokens);
     }
 
+    fn collect_tokens(text: &str) -> Vec<DocToken<'_>> {
+        let test_lexer = XMLLexer::new(text);
+        let mut tokens: Vec<DocToken> = Vec::new();
+        while let Ok(tkn) = test_lexer.next_token() {
+            if let TokenKind::EndOfFile = tkn.kind {
+                break;
+            }
+            tokens.push(tkn);
+        }
+        tokens
+    }
+
+    #[test]
+    fn test_text_directly_followed_by_tag() {
+        let tokens = collect_tokens("abc<x/>");
+
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0], DocToken::new("abc", TokenKind::String, 0));
+        assert!(matches!(tokens[1].kind, TokenKind::Tag(_)));
+    }
+
+    #[test]
+    fn test_adjacent_tags_produce_no_text() {
+        let tokens = collect_tokens("<a/><b/>");
+
+        assert_eq!(tokens.len(), 2);
+        assert!(tokens.iter().all(|t| matches!(t.kind, TokenKind::Tag(_))));
+
+        let root = XMLParser::new("<r>abc<x></x><y></y></r>").parse().unwrap();
+        assert_eq!(*root.content.borrow(), "abc");
+        assert!(root
+            .children
+            .borrow()
+            .iter()
+            .all(|c| c.content.borrow().is_empty()));
+    }
+
+    #[test]
+    fn test_entities_decoded_in_text_and_attributes() {
+        let root = XMLParser::new("<a title='&lt;b&gt; &#x26;'>x&amp;y&#33;</a>")
+            .parse()
+            .unwrap();
+
+        assert_eq!(root.attribute_map_ref()["title"], "<b> &");
+        assert_eq!(*root.content.borrow(), "x&y!");
+    }
+
+    #[test]
+    fn test_attribute_quotes_and_entities_compose() {
+        let root = XMLParser::new(
+            "<a data='He said \"hi\" &amp; left' q='it&apos;s &#39;quoted&#x27;' d=\"&quot;x&quot; &#65;&#x42; 'y'\"></a>",
+        )
+        .parse()
+        .unwrap();
+
+        let attributes = root.attribute_map_ref();
+        assert_eq!(attributes["data"], "He said \"hi\" & left");
+        assert_eq!(attributes["q"], "it's 'quoted'");
+        assert_eq!(attributes["d"], "\"x\" AB 'y'");
+    }
+
+    #[test]
+    fn test_escaped_entities_are_decoded_once() {
+        let root = XMLParser::new("<a v='&amp;lt; &amp;#65;'></a>")
+            .parse()
+            .unwrap();
+
+        assert_eq!(root.attribute_map_ref()["v"], "&lt; &#65;");
+    }
+
+    #[test]
+    fn test_invalid_character_reference_in_text() {
+        match XMLParser::new("<a>ok &#xDFFF;</a>").parse() {
+            Ok(node) => panic!("Expected InvalidCharacterReference, got node: {:?}", node),
+            Err(e) => match e {
+                error::ParseError::InvalidCharacterReference { value, position } => {
+                    assert_eq!(value, 0xDFFF);
+                    assert_eq!(position, 6);
+                }
+                _ => panic!("Expected InvalidCharacterReference, got Err({:?})", e),
+            },
+        }
+    }
+
+    #[test]
+    fn test_invalid_references_in_attributes_point_at_the_reference() {
+        let expect_at =
+            |result: Result<Rc<XMLNode>, error::ParseError>, position: usize| match result {
+                Err(e) => assert_eq!(e.position(), Some(position), "got {:?}", e),
+                Ok(node) => panic!("Expected an error, got node: {:?}", node),
+            };
+
+        let text = "<r>\n  <a id='1'  k = \"ok &#xD800;\"/></r>";
+        expect_at(XMLParser::new(text).parse(), text.find('&').unwrap());
+        match XMLParser::new(text).events().find_map(|e| e.err()) {
+            Some(e) => assert_eq!(e.position(), text.find('&')),
+            None => panic!("Expected an error from the event stream"),
+        }
+
+        let options = ParseOptions {
+            entity_policy: EntityPolicy::Strict,
+            ..Default::default()
+        };
+        let text = "<a k='&bogus;'/>";
+        expect_at(XMLParser::with_options(text, &options).parse(), 6);
+
+        let options = ParseOptions {
+            lenient_attributes: true,
+            ..Default::default()
+        };
+        let text = "<a k=&#xD800;/>";
+        expect_at(XMLParser::with_options(text, &options).parse(), 5);
+
+        let options = ParseOptions {
+            attribute_multimap: true,
+            ..Default::default()
+        };
+        let text = "<a k='&#1114112;' k='fine'/>";
+        expect_at(XMLParser::with_options(text, &options).parse(), 6);
+    }
+
+    #[test]
+    fn test_stats_and_events_fail_like_parse() {
+        let inputs = [
+            "<a><b></a>",
+            "<a><b>",
+            "<a/><b/>",
+            "<a/>x",
+            "x<a/>",
+            "<a><!DOCTYPE a></a>",
+            "<a><?xml version='1.0'?></a>",
+            "<a k='&#xD800;'/>",
+            "<a>&#x110000;</a>",
+            "</a>",
+            "<!-- c --></a><a/>",
+            "",
+        ];
+        for text in inputs {
+            let parsed = XMLParser::new(text).parse().unwrap_err();
+            let streamed = XMLParser::new(text).stats().unwrap_err();
+            assert_eq!(discriminant(&parsed), discriminant(&streamed), "{:?}", text);
+            assert_eq!(parsed.position(), streamed.position(), "{:?}", text);
+            assert!(crate::validate_well_formed(text).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_angular_bracket_inside_attribute_value() {
+        let root = XMLParser::new("<a title='x > y'>text</a>").parse().unwrap();
+        assert_eq!(root.attr("title").as_deref(), Some("x > y"));
+        assert_eq!(*root.content.borrow(), "text");
+
+        let root = XMLParser::new("<a><b rule=\"a>b\" note='say \"hi\">'/></a>")
+            .parse()
+            .unwrap();
+        let child = &root.children.borrow()[0];
+        assert_eq!(child.attr("rule").as_deref(), Some("a>b"));
+        assert_eq!(child.attr("note").as_deref(), Some("say \"hi\">"));
+
+        match XMLParser::new("<a title='x > y>text</a>").parse() {
+            Err(error::ParseError::UnterminatedAngularBracket(position)) => {
+                assert_eq!(position, 0)
+            }
+            result => panic!("Expected UnterminatedAngularBracket, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_doctype_is_skipped() {
+        let simple = "<!DOCTYPE html>\n<html><body>hi</body></html>";
+        let root = XMLParser::new(simple).parse().unwrap();
+        assert_eq!(root.tag.name, "html");
+        assert_eq!(root.text_content(), "hi");
+        assert!(XMLParser::new("<!doctype html><p/>").parse().is_ok());
+
+        let subset = "<?xml version='1.0'?>\n<!DOCTYPE note [\n  <!ELEMENT note (#PCDATA)>\n  <!ENTITY arrow \"->\">\n  <!-- a comment with ] and > -->\n]>\n<note>text</note>";
+        let root = XMLParser::new(subset).parse().unwrap();
+        assert_eq!(root.tag.name, "note");
+        assert_eq!(*root.content.borrow(), "text");
+        assert_eq!(XMLParser::new(subset).stats().unwrap().element_count, 1);
+        assert!(XMLParser::new(subset).events().all(|e| e.is_ok()));
+
+        let external = "<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0//EN\" 'x.dtd'><html/>";
+        assert!(XMLParser::new(external).parse().is_ok());
+    }
+
+    #[test]
+    fn test_internal_subset_entities_are_expanded() {
+        let text = "<!DOCTYPE x [ <!ENTITY foo \"bar\"> ]>\n<x a='&foo;!'>&foo; &lt; &foo;</x>";
+        let options = ParseOptions {
+            entity_policy: EntityPolicy::Strict,
+            ..ParseOptions::default()
+        };
+        let root = XMLParser::with_options(text, &options).parse().unwrap();
+        assert_eq!(*root.content.borrow(), "bar < bar");
+        assert_eq!(root.tag.attributes.borrow()["a"], "bar!");
+
+        let events: Vec<Event> = XMLParser::new(text).events().map(Result::unwrap).collect();
+        assert_eq!(events[1], Event::Text(String::from("bar < bar")));
+
+        // Only the document declaring them knows its entities.
+        match XMLParser::with_options("<x>&foo;</x>", &options).parse() {
+            Err(error::ParseError::UnrecognizedEntity(position)) => assert_eq!(position, 3),
+            result => panic!("Expected UnrecognizedEntity, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_misplaced_or_unterminated_doctype() {
+        match XMLParser::new("<a><!DOCTYPE a></a>").parse() {
+            Err(error::ParseError::MisplacedDoctype(position)) => assert_eq!(position, 3),
+            result => panic!("Expected MisplacedDoctype, got {:?}", result),
+        }
+        match XMLParser::new("<a/><!DOCTYPE a>").stats() {
+            Err(error::ParseError::MisplacedDoctype(position)) => assert_eq!(position, 4),
+            result => panic!("Expected MisplacedDoctype, got {:?}", result),
+        }
+        match XMLParser::new("<!DOCTYPE a [ <!ELEMENT a ANY> <a/>").parse() {
+            Err(error::ParseError::UnterminatedAngularBracket(position)) => {
+                assert_eq!(position, 0)
+            }
+            result => panic!("Expected UnterminatedAngularBracket, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_malformed_comment() {
+        for (text, expected) in [
+            ("<a><!foo></a>", 3),
+            ("<a> <!-bad-> </a>", 4),
+            ("<!- x -> <a>", 0),
+        ] {
+            match XMLParser::new(text).parse() {
+                Ok(node) => panic!("Expected MalformedComment, got node: {:?}", node),
+                Err(e) => match e {
+                    error::ParseError::MalformedComment(pos) => assert_eq!(pos, expected),
+                    _ => panic!("Expected MalformedComment, got Err({:?})", e),
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn test_comments_are_skipped() {
+        let text = "<!-- leading --><a>one<!-- note: <x> & </y> -->two<!--\n  multi\n  line\n--><b></b><!----></a>";
+
+        let root = XMLParser::new(text).parse().unwrap();
+        assert_eq!(*root.content.borrow(), "onetwo");
+        assert_eq!(root.children.borrow().len(), 1);
+        assert_eq!(XMLParser::new(text).stats().unwrap().element_count, 2);
+
+        let comments: Vec<&str> = collect_tokens(text)
+            .iter()
+            .filter_map(|t| match t.kind {
+                TokenKind::Comment(body) => Some(body),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            comments,
+            [" leading ", " note: <x> & </y> ", "\n  multi\n  line\n", ""]
+        );
+    }
+
+    #[test]
+    fn test_cdata_is_taken_verbatim() {
+        let text =
+            "<code>x<![CDATA[if (a < b && c[0]] > 1) { <not a tag> &amp; }]]>y<![CDATA[]]></code>";
+
+        let root = XMLParser::new(text).parse().unwrap();
+        assert_eq!(
+            *root.content.borrow(),
+            "xif (a < b && c[0]] > 1) { <not a tag> &amp; }y"
+        );
+        assert!(root.children.borrow().is_empty());
+
+        let stats = XMLParser::new(text).stats().unwrap();
+        assert_eq!(stats, DocumentStats::from_tree(&root));
+    }
+
+    #[test]
+    fn test_unterminated_cdata() {
+        for (text, expected) in [("<a><![CDATA[ open </a>", 3), ("<a><![CDATA[x]]</a>", 3)] {
+            match XMLParser::new(text).parse() {
+                Ok(node) => panic!("Expected UnterminatedCData, got node: {:?}", node),
+                Err(e) => match e {
+                    error::ParseError::UnterminatedCData(pos) => assert_eq!(pos, expected),
+                    _ => panic!("Expected UnterminatedCData, got Err({:?})", e),
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn test_unterminated_comment() {
+        for (text, expected) in [
+            ("<a><!-- open </a>", 3),
+            ("<!-- x <a>", 0),
+            ("<a><!--></a>", 3),
+        ] {
+            match XMLParser::new(text).parse() {
+                Ok(node) => panic!("Expected UnterminatedComment, got node: {:?}", node),
+                Err(e) => match e {
+                    error::ParseError::UnterminatedComment(pos) => assert_eq!(pos, expected),
+                    _ => panic!("Expected UnterminatedComment, got Err({:?})", e),
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn test_malformed_processing_instruction() {
+        for (text, expected) in [("<a><? ></a>", 3), ("<a><?target></a>", 3), ("<?>", 0)] {
+            match XMLParser::new(text).parse() {
+                Ok(node) => panic!(
+                    "Expected MalformedProcessingInstruction, got node: {:?}",
+                    node
+                ),
+                Err(e) => match e {
+                    error::ParseError::MalformedProcessingInstruction(pos) => {
+                        assert_eq!(pos, expected)
+                    }
+                    _ => panic!("Expected MalformedProcessingInstruction, got Err({:?})", e),
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn test_self_closing_tags_do_not_open_elements() {
+        let text = "<p>one<br/>two<br /><img src='a.png'/></p>";
+
+        let root = XMLParser::new(text).parse().unwrap();
+        let children = root.children.borrow();
+        assert_eq!(children.len(), 3);
+        assert_eq!(children[2].tag.name, "img");
+        assert_eq!(children[2].attribute_map_ref()["src"], "a.png");
+        assert!(children.iter().all(|c| c.children.borrow().is_empty()));
+        assert_eq!(*root.content.borrow(), "onetwo");
+
+        let stats = XMLParser::new(text).stats().unwrap();
+        assert_eq!(stats, DocumentStats::from_tree(&root));
+
+        let lone = XMLParser::new("<empty a='1'/>").parse().unwrap();
+        assert_eq!(lone.tag.name, "empty");
+        assert!(lone.children.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_multibyte_content_round_trips() {
+        let text = "<doc><name lang='fr'>José</name><名前>日本語の名前</名前><e>🦀→ß</e></doc>";
+
+        let root = XMLParser::new(text).parse().unwrap();
+        let children = root.children.borrow();
+        assert_eq!(*children[0].content.borrow(), "José");
+        assert_eq!(children[1].tag.name, "名前");
+        assert_eq!(*children[1].content.borrow(), "日本語の名前");
+        assert_eq!(*children[2].content.borrow(), "🦀→ß");
+
+        // An ideographic space is whitespace, kept as is by default.
+        let spaced = XMLParser::new("<p>\u{3000}é</p>").parse().unwrap();
+        assert_eq!(*spaced.content.borrow(), "\u{3000}é");
+    }
+
+    #[test]
+    fn test_empty_input_has_no_tokens_to_parse() {
+        for text in [
+            "",
+            "   ",
+            "\n\n",
+            "\t\r\n ",
+            "<!-- only a comment -->",
+            "<?xml version='1.0'?>\n",
+        ] {
+            match XMLParser::new(text).parse() {
+                Ok(node) => panic!("Expected NoTokensToParse, got node: {:?}", node),
+                Err(e) => match e {
+                    error::ParseError::NoTokensToParse(position) => {
+                        assert_eq!(position, text.len())
+                    }
+                    _ => panic!("Expected NoTokensToParse for {:?}, got Err({:?})", text, e),
+                },
+            }
+            match XMLParser::new(text).stats() {
+                Ok(stats) => panic!("Expected NoTokensToParse, got {:?}", stats),
+                Err(e) => match e {
+                    error::ParseError::NoTokensToParse(position) => {
+                        assert_eq!(position, text.len())
+                    }
+                    _ => panic!("Expected NoTokensToParse for {:?}, got Err({:?})", text, e),
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn test_stats_of_deep_tree() {
+        let text = format!("{}{}", "<n k='v'>".repeat(100_000), "</n>".repeat(100_000));
+        let options = ParseOptions {
+            max_depth: usize::MAX,
+            ..Default::default()
+        };
+
+        let tree = XMLParser::with_options(&text, &options).parse().unwrap();
+        let stats = DocumentStats::from_tree(&tree);
+        assert_eq!(
+            stats,
+            XMLParser::with_options(&text, &options).stats().unwrap()
+        );
+        assert_eq!(stats.max_depth, 100_000);
+        assert_eq!(stats.attribute_count, 100_000);
+    }
+
+    #[test]
+    fn test_stats_match_tree() {
+        let text = "<lib name='x'><book id='1' lang='en'>Dune &amp; co<ch>one</ch></book><book id='2'><ch><p>deep</p></ch></book></lib>";
+
+        let streamed = XMLParser::new(text).stats().unwrap();
+        let tree = XMLParser::new(text).parse().unwrap();
+
+        assert_eq!(streamed, DocumentStats::from_tree(&tree));
+        assert_eq!(
+            streamed,
+            DocumentStats {
+                element_count: 6,
+                max_depth: 4,
+                attribute_count: 4,
+                text_length: 16,
+            }
+        );
+    }
+
+    #[test]
+    fn test_stats_reports_mismatched_tags() {
+        match XMLParser::new("<a><b></a>").stats() {
+            Ok(stats) => panic!("Expected UnexpectedClosingTag, got {:?}", stats),
+            Err(e) => match e {
+                error::ParseError::UnexpectedClosingTag { expected, .. } => {
+                    assert_eq!(expected, "b")
+                }
+                _ => panic!("Expected UnexpectedClosingTag, got Err({:?})", e),
+            },
+        }
+    }
+
+    #[test]
+    fn test_parse_into_appends_fragments() {
+        let parent = XMLParser::new("<root></root>").parse().unwrap();
+
+        XMLParser::new("<a>1</a> <b>2</b>")
+            .parse_into(&parent)
+            .unwrap();
+        XMLParser::new("<c x='y'>3</c>")
+            .parse_into(&parent)
+            .unwrap();
+
+        let expected = XMLParser::new("<root><a>1</a> <b>2</b><c x='y'>3</c></root>")
+            .parse()
+            .unwrap();
+
+        assert_eq!(parent.to_string(), expected.to_string());
+    }
+
+    #[test]
+    fn test_parse_into_leaves_the_parent_alone_on_error() {
+        let parent = XMLParser::new("<p><x/></p>").parse().unwrap();
+
+        match XMLParser::new("<y/><z></w>").parse_into(&parent) {
+            Err(error::ParseError::UnexpectedClosingTag { .. }) => {}
+            result => panic!("Expected UnexpectedClosingTag, got {:?}", result),
+        }
+        assert_eq!(parent.child_count(), 1);
+        assert_eq!(parent.canonicalize(), "<p><x></x></p>");
+
+        XMLParser::new("a<y/>b").parse_into(&parent).unwrap();
+        assert_eq!(parent.canonicalize(), "<p><x></x>a<y></y>b</p>");
+        assert!(Rc::ptr_eq(
+            &parent.find_first("y").unwrap().parent().unwrap(),
+            &parent
+        ));
+    }
+
+    #[test]
+    fn test_content_after_root_is_an_error() {
+        for (text, position) in [
+            ("<a></a><b></b>", 7),
+            ("<a/><b/>", 4),
+            ("<a></a>\ntail\n", 8),
+            ("<a><b/></a><![CDATA[x]]>", 11),
+        ] {
+            match XMLParser::new(text).parse() {
+                Ok(node) => panic!("Expected ContentOutsideRoot, got node: {:?}", node),
+                Err(e) => match e {
+                    error::ParseError::ContentOutsideRoot { position: found } => {
+                        assert_eq!(found, position, "parsing {:?}", text)
+                    }
+                    _ => panic!(
+                        "Expected ContentOutsideRoot for {:?}, got Err({:?})",
+                        text, e
+                    ),
+                },
+            }
+            match XMLParser::new(text).stats() {
+                Err(error::ParseError::ContentOutsideRoot { position: found }) => {
+                    assert_eq!(found, position, "streaming {:?}", text)
+                }
+                result => panic!(
+                    "Expected ContentOutsideRoot for {:?}, got {:?}",
+                    text, result
+                ),
+            }
+            match XMLParser::new(text).events().last() {
+                Some(Err(error::ParseError::ContentOutsideRoot { position: found })) => {
+                    assert_eq!(found, position, "reading events of {:?}", text)
+                }
+                result => panic!(
+                    "Expected ContentOutsideRoot for {:?}, got {:?}",
+                    text, result
+                ),
+            }
+        }
+
+        let text = "<a>x</a>\n<!-- done -->\n<?pi data?>\n";
+        assert_eq!(*XMLParser::new(text).parse().unwrap().content.borrow(), "x");
+        assert!(XMLParser::new(text).stats().is_ok());
+        assert!(matches!(
+            XMLParser::new(text).events().last(),
+            Some(Ok(Event::Eof))
+        ));
+    }
+
+    #[test]
+    fn test_text_keeps_inner_whitespace() {
+        let lexer = XMLLexer::new("<p>  hello world </p>");
+        lexer.next_token().unwrap();
+        assert!(matches!(
+            lexer.next_token().unwrap().kind,
+            TokenKind::Whitespace
+        ));
+        assert!(matches!(
+            lexer.next_token().unwrap().kind,
+            TokenKind::Whitespace
+        ));
+        let text = lexer.next_token().unwrap();
+        assert_eq!(text, DocToken::new("hello world ", TokenKind::String, 5));
+
+        let root = XMLParser::new("<p>hello world</p>").parse().unwrap();
+        assert_eq!(*root.content.borrow(), "hello world");
+        let root = XMLParser::new("<p>a\n\tb &amp; c<br/>d  e</p>")
+            .parse()
+            .unwrap();
+        assert_eq!(*root.content.borrow(), "a\n\tb & cd  e");
+    }
+
+    #[test]
+    fn test_text_running_to_end_of_input() {
+        let lexer = XMLLexer::new("<a>tail");
+        lexer.next_token().unwrap();
+        let text = lexer.next_token().unwrap();
+        assert_eq!(text, DocToken::new("tail", TokenKind::String, 3));
+        assert!(matches!(
+            lexer.next_token().unwrap().kind,
+            TokenKind::EndOfFile
+        ));
+
+        match XMLParser::new("<a>tail").parse() {
+            Err(error::ParseError::UnclosedTags { names, positions }) => {
+                assert_eq!(names, ["a"]);
+                assert_eq!(positions, [0]);
+            }
+            result => panic!("Expected UnclosedTags, got {:?}", result),
+        }
+        assert!(XMLParser::new("<a>tail").stats().is_err());
+        assert!(XMLParser::new("<a>tail").events().any(|e| e.is_err()));
+
+        match XMLParser::new("<a/>tail").parse() {
+            Err(error::ParseError::ContentOutsideRoot { position }) => assert_eq!(position, 4),
+            result => panic!("Expected ContentOutsideRoot, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_unclosed_tags_at_end_of_input() {
+        match XMLParser::new("<a><b>").parse() {
+            Ok(node) => panic!("Expected UnclosedTags, got node: {:?}", node),
+            Err(e) => {
+                assert_eq!(e.position(), Some(3));
+                match e {
+                    error::ParseError::UnclosedTags { names, positions } => {
+                        assert_eq!(names, ["a", "b"]);
+                        assert_eq!(positions, [0, 3]);
+                    }
+                    _ => panic!("Expected UnclosedTags, got Err({:?})", e),
+                }
+            }
+        }
+        match XMLParser::new("<a><b></a>").parse() {
+            Ok(node) => panic!("Expected UnexpectedClosingTag, got node: {:?}", node),
+            Err(e) => match e {
+                error::ParseError::UnexpectedClosingTag {
+                    expected, obtained, ..
+                } => {
+                    assert_eq!(expected, "b");
+                    assert_eq!(obtained, "a");
+                }
+                _ => panic!("Expected UnexpectedClosingTag, got Err({:?})", e),
+            },
+        }
+        match XMLParser::new("<a><c/><b>").stats() {
+            Err(error::ParseError::UnclosedTags { names, .. }) => assert_eq!(names, ["a", "b"]),
+            result => panic!("Expected UnclosedTags, got {:?}", result),
+        }
+
+        let parent = XMLParser::new("<root/>").parse().unwrap();
+        match XMLParser::new("<x/><y>").parse_into(&parent) {
+            Err(error::ParseError::UnclosedTags { names, positions }) => {
+                assert_eq!(names, ["y"]);
+                assert_eq!(positions, [4]);
+            }
+            result => panic!("Expected UnclosedTags, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_spans_slice_out_elements() {
+        let text = "<?xml version='1.0'?>\n<lib>\n  <book id='1'>Dune <b>x</b></book>\n  <empty  />\n</lib>\n";
+        let root = XMLParser::new(text).parse().unwrap();
+        let slice = |node: &XMLNode| {
+            let (start, end) = node.span();
+            &text[start..end]
+        };
+
+        assert!(slice(&root).starts_with("<lib>") && slice(&root).ends_with("</lib>"));
+        assert_eq!(
+            slice(&root.find_first("book").unwrap()),
+            "<book id='1'>Dune <b>x</b></book>"
+        );
+        assert_eq!(slice(&root.find_first("b").unwrap()), "<b>x</b>");
+        assert_eq!(slice(&root.find_first("empty").unwrap()), "<empty  />");
+
+        let single = "  <only/> ";
+        assert_eq!(XMLParser::new(single).parse().unwrap().span(), (2, 9));
+    }
+
+    #[test]
+    fn test_parent_links_lead_back_to_root() {
+        let root = XMLParser::new("<a><b/><c><d><e>deep</e></d></c></a>")
+            .parse()
+            .unwrap();
+        assert!(root.parent().is_none());
+
+        let mut names = Vec::new();
+        let mut node = root.find_first("e");
+        while let Some(current) = node {
+            names.push(current.tag.name.clone());
+            node = current.parent();
+        }
+        assert_eq!(names, ["e", "d", "c", "a"]);
+
+        let b = root.find_first("b").unwrap();
+        assert!(Rc::ptr_eq(&b.parent().unwrap(), &root));
+
+        XMLParser::new("<f/>").parse_into(&b).unwrap();
+        assert!(Rc::ptr_eq(
+            &root.find_first("f").unwrap().parent().unwrap(),
+            &b
+        ));
+
+        // The link is weak, it does not keep a dropped tree alive.
+        let e = root.find_first("e").unwrap();
+        drop((root, b));
+        assert!(e.parent().is_none());
+    }
+
+    #[test]
+    fn test_closing_tag_before_the_root() {
+        match XMLParser::new("<!-- c --></a><a/>").parse() {
+            Err(error::ParseError::ClosingTagNeverOpened { obtained, position }) => {
+                assert_eq!(obtained, "a");
+                assert_eq!(position, 10);
+            }
+            result => panic!("Expected ClosingTagNeverOpened, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_parse_into_rejects_closing_the_parent() {
+        let parent = XMLParser::new("<root></root>").parse().unwrap();
+
+        match XMLParser::new("<a></a></root>").parse_into(&parent) {
+            Ok(_) => panic!("Expected ClosingTagNeverOpened, got Ok"),
+            Err(e) => match e {
+                error::ParseError::ClosingTagNeverOpened { obtained, position } => {
+                    assert_eq!(obtained, "root");
+                    assert_eq!(position, 7);
+                }
+                _ => panic!("Expected ClosingTagNeverOpened, got Err({:?})", e),
+            },
+        }
+    }
+
+    #[test]
+    fn test_empty_tags_are_errors() {
+        for text in ["<>", "< >", "<a><></a>", "<a>< ></a>"] {
+            match XMLParser::new(text).parse() {
+                Ok(node) => panic!("Expected EmptyTag, got node: {:?}", node),
+                Err(e) => match e {
+                    error::ParseError::TagParseError(error::TagParseError::EmptyTag) => {}
+                    _ => panic!("Expected EmptyTag for {:?}, got Err({:?})", text, e),
+                },
+            }
+        }
+    }
+
     #[test]
     fn test_tag_tokenization_failure_unterminated_angular_bracket() {
         let text = "<xml> <oopsi problem here";
@@ -325,7 +1813,7 @@ mod tests {
                 )),
                 6,
             ),
-            DocToken::new("David", TokenKind::String, 28),
+            DocToken::new("David   ", TokenKind::String, 28),
             DocToken::new(
                 "< / person >",
                 TokenKind::Tag(BaseXMLTag::new(
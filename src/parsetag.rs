@@ -4,7 +4,8 @@ use std::cmp::PartialEq;
 use std::collections::HashMap;
 use std::mem::discriminant;
 
-#[derive(Debug, Clone)]enum TokenKind {
+#[derive(Debug, Clone)]
+enum TokenKind {
     String,
     StringLiteral,
     Equals,
@@ -12,10 +13,12 @@ use std::mem::discriminant;
     Whitespace,
     EndOfLine,
     ForwardSlash,
+    /// A lenient attribute value written without quotes.
+    UnquotedValue,
 }
 
 #[derive(Debug, Clone)]
-struct TagToken<'a> {
+pub(crate) struct TagToken<'a> {
     kind: TokenKind,
     text: &'a str,
     _position: usize,
@@ -41,12 +44,18 @@ impl<'a> PartialEq for TagToken<'a> {
 pub struct TagLexer<'a> {
     content: &'a str,
     position: RefCell<usize>,
+    /// Lex what follows an `=` up to the next whitespace as an unquoted value.
+    lenient: bool,
+    /// Whether the last token other than whitespace was an `=`.
+    after_equals: RefCell<bool>,
 }
 impl<'a> TagLexer<'a> {
     fn new(content: &'a str) -> Self {
         Self {
             content,
             position: RefCell::new(0),
+            lenient: false,
+            after_equals: RefCell::new(false),
         }
     }
     fn cur(&self) -> usize {
@@ -56,29 +65,51 @@ impl<'a> TagLexer<'a> {
         if self.end() {
             return '\0';
         }
-        self.content.as_bytes()[self.cur()] as char
+        self.content[self.cur()..].chars().next().unwrap_or('\0')
     }
+    /// Steps over the current character, however many bytes it takes.
     fn next(&self) {
-        *self.position.borrow_mut() += 1;
+        *self.position.borrow_mut() += self.current().len_utf8();
     }
     fn end(&self) -> bool {
         *self.position.borrow() >= self.content.len()
     }
-    fn next_token(&self) -> Result<TagToken, error::TagParseError> {
+    fn next_token(&self) -> Result<TagToken<'a>, error::TagParseError> {
         let start = self.cur();
+        let after_equals = self.after_equals.replace(false);
         if self.end() {
-            return Ok(TagToken::new(
-                &self.content[self.content.len() - 1..self.content.len() - 1],
+            Ok(TagToken::new(
+                &self.content[self.content.len()..],
                 TokenKind::EndOfLine,
                 self.content.len(),
-            ));
+            ))
         } else if self.current().is_whitespace() {
+            *self.after_equals.borrow_mut() = after_equals;
             self.next();
-            return Ok(TagToken::new(
-                &self.content[start..start + 1],
+            Ok(TagToken::new(
+                &self.content[start..self.cur()],
                 TokenKind::Whitespace,
                 start,
-            ));
+            ))
+        } else if after_equals
+            && self.lenient
+            && self.current() != '\''
+            && self.current() != '"'
+            && &self.content[start..] != "/"
+        {
+            // Slashes belong to the value, as in `href=http://x/y`, except
+            // one ending the tag, as in `colspan=2/`.
+            while !self.end() && !self.current().is_whitespace() {
+                self.next();
+            }
+            if self.end() && self.content.ends_with('/') {
+                *self.position.borrow_mut() -= 1;
+            }
+            Ok(TagToken::new(
+                &self.content[start..self.cur()],
+                TokenKind::UnquotedValue,
+                start,
+            ))
         } else if self.current() == '\'' || self.current() == '"' {
             let quote_type = self.current();
 
@@ -94,36 +125,43 @@ impl<'a> TagLexer<'a> {
 
             let end = self.cur();
 
-            return Ok(TagToken::new(
+            Ok(TagToken::new(
                 &self.content[start..end],
                 TokenKind::StringLiteral,
                 start,
-            ));
+            ))
         } else if self.current().is_alphabetic() || self.current() == '_' {
-            while !self.end() && (self.current().is_alphanumeric() || self.current() == '_') {
+            while !self.end()
+                && (self.current().is_alphanumeric()
+                    || self.current() == '_'
+                    || self.current() == '-'
+                    || self.current() == '.'
+                    || self.current() == ':')
+            {
                 self.next();
             }
 
-            return Ok(TagToken::new(
+            Ok(TagToken::new(
                 &self.content[start..self.cur()],
                 TokenKind::String,
                 start,
-            ));
+            ))
         } else if self.current() == '=' {
+            *self.after_equals.borrow_mut() = true;
             self.next();
 
-            return Ok(TagToken::new(
+            Ok(TagToken::new(
                 &self.content[start..start + 1],
                 TokenKind::Equals,
                 start,
-            ));
+            ))
         } else if self.current() == '/' {
             self.next();
-            return Ok(TagToken::new(
+            Ok(TagToken::new(
                 &self.content[start..start + 1],
                 TokenKind::ForwardSlash,
                 start,
-            ));
+            ))
         } else {
             self.next();
 
@@ -131,24 +169,48 @@ impl<'a> TagLexer<'a> {
                 self.next();
             }
 
-            return Ok(TagToken::new(
+            Ok(TagToken::new(
                 &self.content[start..self.cur()],
                 TokenKind::Unknown,
                 start,
-            ));
+            ))
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// Strips the surrounding quotes off a `StringLiteral` token, checking that it
+/// opens and closes with the same quote character.
+fn unquote<'a>(literal: &TagToken<'a>) -> Result<&'a str, error::TagParseError> {
+    let mut chars = literal.text.chars();
+    match (chars.next(), chars.next_back()) {
+        (Some(open), Some(close)) if open == close && (open == '\'' || open == '"') => {
+            Ok(chars.as_str())
+        }
+        _ => Err(error::TagParseError::MalformedStringLiteral(
+            literal._position,
+        )),
+    }
+}
+
+/// Which of the three forms an element tag is written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum TagKind {
     Opening,
     Closing,
+    /// An element without content written as a single tag, `<br/>`.
+    SelfClosing,
 }
 #[derive(Debug, Clone)]
 pub struct BaseXMLTag {
     pub name: String,
     pub attribs: HashMap<String, String>,
+    /// Values of attributes written more than once that were overwritten by a
+    /// later occurrence, in source order, with the document offset of each.
+    pub repeated: Vec<(String, String, usize)>,
+    /// Document offset at which the value of each attribute starts, for
+    /// errors found while decoding it.
+    pub value_positions: HashMap<String, usize>,
     pub kind: TagKind,
     pub pos: usize,
 }
@@ -158,6 +220,8 @@ impl BaseXMLTag {
         Self {
             name,
             attribs,
+            repeated: Vec::new(),
+            value_positions: HashMap::new(),
             kind,
             pos,
         }
@@ -168,11 +232,20 @@ impl PartialEq for BaseXMLTag {
     fn eq(&self, other: &Self) -> bool {
         self.name == other.name
             && self.attribs == other.attribs
+            && self.repeated == other.repeated
             && self.pos == other.pos
-            && discriminant(&self.kind) == discriminant(&other.kind)
+            && self.kind == other.kind
     }
 }
 
+/// Empties `tokens` and hands its allocation back for the tokens of another
+/// input. The element type only differs in lifetime, so collecting happens
+/// in place and keeps the buffer.
+pub(crate) fn recycle<'b>(mut tokens: Vec<TagToken<'_>>) -> Vec<TagToken<'b>> {
+    tokens.clear();
+    tokens.into_iter().map(|_| unreachable!()).collect()
+}
+
 #[derive(Debug)]
 pub struct TagParser<'a> {
     content: &'a str,
@@ -180,6 +253,9 @@ pub struct TagParser<'a> {
     position: RefCell<usize>,
     tokens: RefCell<Vec<TagToken<'a>>>,
     doc_pos: usize,
+    /// Document offset of `content`, past the `<` when that was stripped.
+    content_pos: usize,
+    lenient: bool,
 }
 
 impl<'a> TagParser<'a> {
@@ -194,6 +270,8 @@ impl<'a> TagParser<'a> {
                 position: RefCell::new(0),
                 tokens,
                 doc_pos,
+                content_pos: doc_pos + 1,
+                lenient: false,
             };
         }
         let lexer = TagLexer::new(content);
@@ -203,10 +281,33 @@ impl<'a> TagParser<'a> {
             position: RefCell::new(0),
             tokens,
             doc_pos,
+            content_pos: doc_pos,
+            lenient: false,
         }
     }
+    /// Accepts HTML style attributes: a bare name like `disabled` becomes an
+    /// attribute with an empty value and `colspan=2` takes everything after
+    /// `=` up to the next whitespace as its value, but for a `/` ending the
+    /// tag.
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self.lexer.lenient = lenient;
+        self
+    }
 
-    fn tokenize(&'a self) -> Result<(), error::TagParseError> {
+    /// Lexes into `tokens` instead of a new buffer so its allocation is
+    /// reused, see `into_tokens`.
+    pub(crate) fn reusing(self, mut tokens: Vec<TagToken<'a>>) -> Self {
+        tokens.clear();
+        self.tokens.replace(tokens);
+        self
+    }
+    /// Gives back the token buffer, to be handed to the next `reusing`.
+    pub(crate) fn into_tokens(self) -> Vec<TagToken<'a>> {
+        self.tokens.into_inner()
+    }
+
+    fn tokenize(&self) -> Result<(), error::TagParseError> {
         loop {
             let cur_token = self.lexer.next_token()?;
 
@@ -223,20 +324,20 @@ impl<'a> TagParser<'a> {
         Ok(())
     }
 
-    fn peek(&self, offset: i64) -> Result<Ref<'a, TagToken>, error::TagParseError> {
+    fn peek(&self, offset: i64) -> Result<Ref<'_, TagToken<'a>>, error::TagParseError> {
         let pos_copy = *self.position.borrow() as i64;
         if pos_copy + offset < 1 || pos_copy + offset >= self.tokens.borrow().len() as i64 {
             return Err(error::TagParseError::PeekOutOfBounds {
-                offset: offset,
+                offset,
                 cur_idx: *self.position.borrow(),
                 len: self.content.len(),
             });
         }
         let idx = (pos_copy + offset) as usize;
-        return Ok(Ref::map(self.tokens.borrow(), |tkns| &tkns[idx]));
+        Ok(Ref::map(self.tokens.borrow(), |tkns| &tkns[idx]))
     }
 
-    fn cur_token(&self) -> Ref<'a, TagToken> {
+    fn cur_token(&self) -> Ref<'_, TagToken<'a>> {
         Ref::map(self.tokens.borrow(), |tkns| &tkns[*self.position.borrow()])
     }
 
@@ -248,20 +349,51 @@ impl<'a> TagParser<'a> {
         *self.position.borrow() >= self.tokens.borrow().len()
     }
 
-    pub fn parse(&'a self) -> Result<BaseXMLTag, error::TagParseError> {
+    /// Whether the current token is a name standing alone, neither side of
+    /// an `=`.
+    fn is_bare_name(&self) -> bool {
+        let tokens = self.tokens.borrow();
+        let position = *self.position.borrow();
+        let is_equals = |index: Option<usize>| {
+            index
+                .and_then(|i| tokens.get(i))
+                .is_some_and(|t| matches!(t.kind, TokenKind::Equals))
+        };
+        matches!(tokens[position].kind, TokenKind::String)
+            && !is_equals(position.checked_sub(1))
+            && !is_equals(Some(position + 1))
+    }
+
+    pub fn parse(&self) -> Result<BaseXMLTag, error::TagParseError> {
         self.tokenize()?;
+        if self.end() {
+            return Err(error::TagParseError::EmptyTag);
+        }
         let first = self.cur_token();
 
         let name: String;
         let kind: TagKind;
 
         if let TokenKind::String = first.kind {
-            kind = TagKind::Opening;
+            // A slash can only end an opening tag, the one of a closing tag
+            // comes first.
+            let self_closing = self.tokens.borrow().len() > 1
+                && matches!(
+                    self.tokens.borrow().last().map(|t| &t.kind),
+                    Some(TokenKind::ForwardSlash)
+                );
+            kind = match self_closing {
+                true => TagKind::SelfClosing,
+                false => TagKind::Opening,
+            };
             name = String::from(first.text);
         } else if let TokenKind::ForwardSlash = first.kind {
             kind = TagKind::Closing;
             self.next();
 
+            if self.end() {
+                return Err(error::TagParseError::InvalidFirstToken);
+            }
             let second = self.cur_token();
 
             if let TokenKind::String = second.kind {
@@ -274,9 +406,28 @@ impl<'a> TagParser<'a> {
         }
 
         let mut attribs: HashMap<String, String> = HashMap::new();
+        let mut repeated: Vec<(String, String, usize)> = Vec::new();
+        let mut value_positions: HashMap<String, usize> = HashMap::new();
+        let mut insert = |k: String, v: String, position: usize| {
+            let position = self.content_pos + position;
+            let old_position = value_positions.insert(k.clone(), position);
+            if let (Some(old), Some(old_position)) = (attribs.insert(k.clone(), v), old_position) {
+                repeated.push((k, old, old_position));
+            }
+        };
+        let name_index = *self.position.borrow();
 
         while !self.end() {
             let cur = self.cur_token();
+            if self.lenient && *self.position.borrow() != name_index && self.is_bare_name() {
+                insert(String::from(cur.text), String::new(), cur._position);
+            }
+            if let TokenKind::Unknown = cur.kind {
+                return Err(error::TagParseError::UnexpectedCharacter {
+                    character: cur.text.chars().next().unwrap_or('\0'),
+                    position: cur._position,
+                });
+            }
             if let TokenKind::Equals = cur.kind {
                 let left = match self.peek(-1) {
                     Ok(tkn) => tkn,
@@ -298,17 +449,30 @@ impl<'a> TagParser<'a> {
                         });
                     }
                 };
-                if let (TokenKind::String, TokenKind::StringLiteral) = (&left.kind, &right.kind) {
-                    let k = String::from(left.text);
-                    let v = String::from(&right.text[1..right.text.len() - 1]);
-                    attribs.insert(k, v);
-                } else {
-                    return Err(error::TagParseError::UnexpectedTagToken);
+                match (&left.kind, &right.kind) {
+                    (TokenKind::String, TokenKind::StringLiteral) => {
+                        insert(
+                            String::from(left.text),
+                            String::from(unquote(&right)?),
+                            right._position + 1,
+                        );
+                    }
+                    (TokenKind::String, TokenKind::UnquotedValue) => {
+                        insert(
+                            String::from(left.text),
+                            String::from(right.text),
+                            right._position,
+                        );
+                    }
+                    _ => return Err(error::TagParseError::UnexpectedTagToken),
                 }
             }
             self.next();
         }
-        Ok(BaseXMLTag::new(name, attribs, kind, self.doc_pos))
+        let mut tag = BaseXMLTag::new(name, attribs, kind, self.doc_pos);
+        tag.repeated = repeated;
+        tag.value_positions = value_positions;
+        Ok(tag)
     }
 }
 
@@ -486,6 +650,7 @@ mod tests {
         actual_attribs.insert(String::from("attribute1"), String::from("value1"));
 
         assert_eq!(test_tag.name, String::from("tagname"));
+        assert_eq!(test_tag.kind, TagKind::Opening);
         assert_eq!(test_tag.attribs, actual_attribs);
     }
 
@@ -518,10 +683,7 @@ mod tests {
 
         assert_eq!(test_tag.name, "tagname");
 
-        match test_tag.kind {
-            TagKind::Closing => {}
-            _ => panic!("Inputted closing tag string, got opening tag output"),
-        }
+        assert_eq!(test_tag.kind, TagKind::Closing);
     }
 
     #[test]
@@ -544,7 +706,170 @@ mod tests {
         );
 
         assert_eq!(obtained_tag, actual_tag);
+    }
+
+    #[test]
+    fn test_attribute_parsing_multibyte_value() {
+        let text = "<person name='éJosé 日本語 ñ' city=\"東京\">";
+
+        let test_parser = TagParser::new(text, 0);
+        let obtained_tag = test_parser.parse().unwrap();
+
+        assert_eq!(obtained_tag.attribs["name"], "éJosé 日本語 ñ");
+        assert_eq!(obtained_tag.attribs["city"], "東京");
+    }
+
+    #[test]
+    fn test_unquote_rejects_malformed_literal() {
+        let mismatched = TagToken::new("'value\"", TokenKind::StringLiteral, 4);
+        let unopened = TagToken::new("value'", TokenKind::StringLiteral, 7);
+        let lone_quote = TagToken::new("'", TokenKind::StringLiteral, 9);
+
+        for token in [mismatched, unopened, lone_quote] {
+            match unquote(&token) {
+                Ok(v) => panic!("Expected MalformedStringLiteral, got Ok({})", v),
+                Err(e) => match e {
+                    TagParseError::MalformedStringLiteral(pos) => assert_eq!(pos, token._position),
+                    _ => panic!("Expected MalformedStringLiteral, got Err({:?})", e),
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn test_attribute_names_with_hyphen_and_dot() {
+        let text = "<tagname data-foo='1' v.2='2'>";
+
+        let test_parser = TagParser::new(text, 0);
+        let obtained_tag = test_parser.parse().unwrap();
 
+        assert_eq!(obtained_tag.attribs["data-foo"], "1");
+        assert_eq!(obtained_tag.attribs["v.2"], "2");
+    }
+
+    #[test]
+    fn test_unexpected_character_is_reported() {
+        let text = "<tagname attrib1='v' `oops>";
+
+        let test_parser = TagParser::new(text, 0);
+
+        match test_parser.parse() {
+            Ok(tag) => panic!("Expected UnexpectedCharacter, got tag: {:?}", tag),
+            Err(e) => {
+                match e {
+                    TagParseError::UnexpectedCharacter {
+                        character,
+                        position,
+                    } => {
+                        assert_eq!(character, '`');
+                        assert_eq!(position, 20);
+                    }
+                    _ => panic!("Expected UnexpectedCharacter, got Err({:?})", e),
+                }
+                assert!(e.to_string().contains('`'));
+            }
+        }
+    }
+
+    #[test]
+    fn test_tag_whitespace_boundaries() {
+        let cases = [
+            ("<tagname   >", TagKind::Opening, 0),
+            ("<   tagname>", TagKind::Opening, 0),
+            ("<\ttagname\n>", TagKind::Opening, 0),
+            ("< tagname   attr  =   'v'   >", TagKind::Opening, 1),
+            ("</tagname   >", TagKind::Closing, 0),
+            ("<  /  tagname  >", TagKind::Closing, 0),
+        ];
+
+        for (text, kind, attrib_count) in cases {
+            let test_parser = TagParser::new(text, 0);
+            let tag = test_parser.parse().unwrap();
+
+            assert_eq!(tag.name, "tagname", "parsing {:?}", text);
+            assert_eq!(tag.kind, kind, "parsing {:?}", text);
+            assert_eq!(tag.attribs.len(), attrib_count, "parsing {:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_multibyte_tag_names() {
+        for (text, name) in [
+            ("<名前>", "名前"),
+            ("</café>", "café"),
+            ("<naïve\u{3000}a='1'/>", "naïve"),
+        ] {
+            let test_parser = TagParser::new(text, 0);
+            let tag = test_parser.parse().unwrap();
+
+            assert_eq!(tag.name, name, "parsing {:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_closing_slash_followed_by_equals() {
+        for text in ["</=>", "</ = 'x'>", "</='x'>"] {
+            let test_parser = TagParser::new(text, 0);
+
+            match test_parser.parse() {
+                Ok(tag) => panic!("Expected InvalidFirstToken, got tag: {:?}", tag),
+                Err(e) => match e {
+                    TagParseError::InvalidFirstToken => {}
+                    _ => panic!(
+                        "Expected InvalidFirstToken for {:?}, got Err({:?})",
+                        text, e
+                    ),
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn test_self_closing_tags() {
+        let cases = [
+            ("<br/>", "br", TagKind::SelfClosing, 0),
+            ("<br />", "br", TagKind::SelfClosing, 0),
+            ("<br attr='v'/>", "br", TagKind::SelfClosing, 1),
+            ("<a href='x/y'>", "a", TagKind::Opening, 1),
+            ("</br>", "br", TagKind::Closing, 0),
+        ];
+
+        for (text, name, kind, attrib_count) in cases {
+            let test_parser = TagParser::new(text, 0);
+            let tag = test_parser.parse().unwrap();
+
+            assert_eq!(tag.name, name, "parsing {:?}", text);
+            assert_eq!(tag.kind, kind, "parsing {:?}", text);
+            assert_eq!(tag.attribs.len(), attrib_count, "parsing {:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_whitespace_only_tag_is_empty() {
+        for text in ["<>", "< >", "<   >", "< \n\t >"] {
+            let test_parser = TagParser::new(text, 0);
+
+            match test_parser.parse() {
+                Ok(tag) => panic!("Expected EmptyTag, got tag: {:?}", tag),
+                Err(e) => match e {
+                    TagParseError::EmptyTag => {}
+                    _ => panic!("Expected EmptyTag, got Err({:?})", e),
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn test_closing_tag_without_name() {
+        let test_parser = TagParser::new("</   >", 0);
+
+        match test_parser.parse() {
+            Ok(tag) => panic!("Expected InvalidFirstToken, got tag: {:?}", tag),
+            Err(e) => match e {
+                TagParseError::InvalidFirstToken => {}
+                _ => panic!("Expected InvalidFirstToken, got Err({:?})", e),
+            },
+        }
     }
 
     #[test]
@@ -614,4 +939,89 @@ mod tests {
             },
         }
     }
+
+    #[test]
+    fn test_prefixed_names() {
+        let text = "<ns:book xmlns:ns='urn:books' xlink:href='#a' plain='1'/>";
+
+        let tag = TagParser::new(text, 0).parse().unwrap();
+        assert_eq!(tag.name, "ns:book");
+        assert_eq!(tag.kind, TagKind::SelfClosing);
+        assert_eq!(
+            tag.attribs.get("xmlns:ns").map(String::as_str),
+            Some("urn:books")
+        );
+        assert_eq!(
+            tag.attribs.get("xlink:href").map(String::as_str),
+            Some("#a")
+        );
+        assert_eq!(tag.attribs.len(), 3);
+
+        let tag = TagParser::new("</ns:book>", 0).parse().unwrap();
+        assert_eq!(tag.name, "ns:book");
+        assert_eq!(tag.kind, TagKind::Closing);
+    }
+
+    #[test]
+    fn test_lenient_attributes() {
+        let text = "<tagname var1 = unquoted flag var2='q' other>";
+
+        let tag = TagParser::new(text, 0).lenient(true).parse().unwrap();
+        assert_eq!(tag.name, "tagname");
+        assert_eq!(
+            tag.attribs.get("var1").map(String::as_str),
+            Some("unquoted")
+        );
+        assert_eq!(tag.attribs.get("flag").map(String::as_str), Some(""));
+        assert_eq!(tag.attribs.get("var2").map(String::as_str), Some("q"));
+        assert_eq!(tag.attribs.get("other").map(String::as_str), Some(""));
+        assert_eq!(tag.attribs.len(), 4);
+
+        let tag = TagParser::new("<td width=50%>", 0)
+            .lenient(true)
+            .parse()
+            .unwrap();
+        assert_eq!(tag.attribs.get("width").map(String::as_str), Some("50%"));
+
+        let tag = TagParser::new("<a href=http://x/y>", 0)
+            .lenient(true)
+            .parse()
+            .unwrap();
+        assert_eq!(
+            tag.attribs.get("href").map(String::as_str),
+            Some("http://x/y")
+        );
+        assert_eq!(tag.attribs.len(), 1);
+        assert_eq!(tag.kind, TagKind::Opening);
+
+        let tag = TagParser::new("<td colspan=2/>", 0)
+            .lenient(true)
+            .parse()
+            .unwrap();
+        assert_eq!(tag.attribs.get("colspan").map(String::as_str), Some("2"));
+        assert_eq!(tag.kind, TagKind::SelfClosing);
+
+        let tag = TagParser::new("<img src=/a/b.png />", 0)
+            .lenient(true)
+            .parse()
+            .unwrap();
+        assert_eq!(tag.attribs.get("src").map(String::as_str), Some("/a/b.png"));
+        assert_eq!(tag.kind, TagKind::SelfClosing);
+    }
+
+    #[test]
+    fn test_token_buffer_is_reused() {
+        let parser = TagParser::new("<a b='1' c='2'>", 0);
+        parser.parse().unwrap();
+        let tokens = parser.into_tokens();
+        let (pointer, capacity) = (tokens.as_ptr() as usize, tokens.capacity());
+
+        let text = String::from("<d/>");
+        let parser = TagParser::new(&text, 0).reusing(recycle(tokens));
+        assert_eq!(parser.parse().unwrap().name, "d");
+        let tokens = parser.into_tokens();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens.as_ptr() as usize, pointer);
+        assert_eq!(tokens.capacity(), capacity);
+    }
 }
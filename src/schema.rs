@@ -0,0 +1,187 @@
+use crate::api::XMLNode;
+use crate::error::ValidationError;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// What an element is allowed to contain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentModel {
+    /// Neither text nor child elements.
+    Empty,
+    /// Text but no child elements.
+    TextOnly,
+    /// Child elements but no text, whitespace-only text is tolerated.
+    ElementsOnly,
+    /// Anything goes.
+    Mixed,
+}
+
+/// Per-tag structural rules checked by `validate_schema`. Tags without a
+/// registered content model are treated as `ContentModel::Mixed`.
+#[derive(Debug, Default, Clone)]
+pub struct Schema {
+    content_models: HashMap<String, ContentModel>,
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Self {
+            content_models: HashMap::new(),
+        }
+    }
+    pub fn set_content_model(&mut self, tag: &str, model: ContentModel) {
+        self.content_models.insert(String::from(tag), model);
+    }
+    pub fn content_model(&self, tag: &str) -> ContentModel {
+        match self.content_models.get(tag) {
+            Some(model) => *model,
+            None => ContentModel::Mixed,
+        }
+    }
+}
+
+/// Checks every element of the tree rooted at `root` against `schema` and
+/// returns all violations found, an empty vector means the tree is valid.
+/// Works iteratively so deep trees can be validated.
+pub fn validate_schema(root: &XMLNode, schema: &Schema) -> Vec<ValidationError> {
+    let mut violations: Vec<ValidationError> = Vec::new();
+    let mut pending: Vec<Rc<XMLNode>> = check_node(root, schema, &mut violations);
+
+    while let Some(node) = pending.pop() {
+        pending.extend(check_node(&node, schema, &mut violations));
+    }
+    violations
+}
+
+/// Checks the text of `node` and that it may hold its children, returning
+/// the children in reverse order for the caller to check next.
+fn check_node(
+    node: &XMLNode,
+    schema: &Schema,
+    violations: &mut Vec<ValidationError>,
+) -> Vec<Rc<XMLNode>> {
+    let model = schema.content_model(&node.tag.name);
+
+    let allows_text = matches!(model, ContentModel::TextOnly | ContentModel::Mixed);
+    let allows_children = matches!(model, ContentModel::ElementsOnly | ContentModel::Mixed);
+
+    if !allows_text && !node.content.borrow().trim().is_empty() {
+        violations.push(ValidationError::TextNotAllowed {
+            tag: node.tag.name.to_owned(),
+            position: node.tag._pos,
+        });
+    }
+
+    let children = node.cloned_children();
+    if !allows_children {
+        for child in children.iter() {
+            violations.push(ValidationError::ChildNotAllowed {
+                tag: node.tag.name.to_owned(),
+                child: child.tag.name.to_owned(),
+                position: child.tag._pos,
+            });
+        }
+    }
+    children.into_iter().rev().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsedoc::XMLParser;
+
+    fn schema() -> Schema {
+        let mut schema = Schema::new();
+        schema.set_content_model("library", ContentModel::ElementsOnly);
+        schema.set_content_model("book", ContentModel::ElementsOnly);
+        schema.set_content_model("title", ContentModel::TextOnly);
+        schema.set_content_model("hr", ContentModel::Empty);
+        schema
+    }
+
+    fn validate(text: &str) -> Vec<ValidationError> {
+        let root = XMLParser::new(text).parse().unwrap();
+        validate_schema(&root, &schema())
+    }
+
+    #[test]
+    fn test_valid_document() {
+        let violations = validate(
+            "<library> <book> <title>Dune</title> <hr></hr> </book> <note>a <b>mixed</b></note> </library>",
+        );
+        assert!(
+            violations.is_empty(),
+            "Unexpected violations: {:?}",
+            violations
+        );
+    }
+
+    #[test]
+    fn test_text_in_elements_only() {
+        let violations = validate("<library>stray<book></book></library>");
+
+        match violations.as_slice() {
+            [ValidationError::TextNotAllowed { tag, position }] => {
+                assert_eq!(tag, "library");
+                assert_eq!(*position, 0);
+            }
+            _ => panic!("Expected a single TextNotAllowed, got {:?}", violations),
+        }
+    }
+
+    #[test]
+    fn test_children_in_text_only() {
+        let violations = validate("<library><book><title>Dune<i>!</i></title></book></library>");
+
+        match violations.as_slice() {
+            [ValidationError::ChildNotAllowed {
+                tag,
+                child,
+                position,
+            }] => {
+                assert_eq!(tag, "title");
+                assert_eq!(child, "i");
+                assert_eq!(*position, 26);
+            }
+            _ => panic!("Expected a single ChildNotAllowed, got {:?}", violations),
+        }
+    }
+
+    #[test]
+    fn test_text_and_children_in_empty() {
+        let violations = validate("<library><book><hr>oops<br></br></hr></book></library>");
+
+        match violations.as_slice() {
+            [ValidationError::TextNotAllowed { tag: text_tag, .. }, ValidationError::ChildNotAllowed {
+                tag: child_tag,
+                child,
+                ..
+            }] => {
+                assert_eq!(text_tag, "hr");
+                assert_eq!(child_tag, "hr");
+                assert_eq!(child, "br");
+            }
+            _ => panic!(
+                "Expected TextNotAllowed and ChildNotAllowed, got {:?}",
+                violations
+            ),
+        }
+    }
+
+    #[test]
+    fn test_deep_tree() {
+        let root = XMLNode::element("n").build();
+        let mut deepest = Rc::clone(&root);
+        for _ in 0..100_000 {
+            let child = XMLNode::element("n").build();
+            deepest.append_child(Rc::clone(&child));
+            deepest = child;
+        }
+        deepest.push_content("text");
+        let mut schema = Schema::new();
+        schema.set_content_model("n", ContentModel::ElementsOnly);
+
+        let violations = validate_schema(&root, &schema);
+        assert_eq!(violations.len(), 1);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::api::XMLNode;
+
+/// One step of a selector such as `item.sale#first`, all of whose parts must
+/// hold for an element to match.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Compound {
+    name: Option<String>,
+    classes: Vec<String>,
+    id: Option<String>,
+}
+
+impl Compound {
+    pub(crate) fn matches(&self, node: &XMLNode) -> bool {
+        if self
+            .name
+            .as_ref()
+            .is_some_and(|name| *name != node.tag.name)
+        {
+            return false;
+        }
+        let attributes = node.attribute_map_ref();
+        if let Some(id) = &self.id {
+            if attributes.get("id") != Some(id) {
+                return false;
+            }
+        }
+        let classes: Vec<&str> = match attributes.get("class") {
+            Some(class) => class.split_whitespace().collect(),
+            None => Vec::new(),
+        };
+        self.classes
+            .iter()
+            .all(|class| classes.contains(&class.as_str()))
+    }
+}
+
+/// Splits a selector into the compounds separated by the descendant
+/// combinator, a run of whitespace. Each compound is an optional element name
+/// or `*`, followed by any number of `.class` and `#id` parts. `None` when the
+/// selector is empty or a part is.
+pub(crate) fn parse(selector: &str) -> Option<Vec<Compound>> {
+    let compounds: Option<Vec<Compound>> =
+        selector.split_whitespace().map(parse_compound).collect();
+    compounds.filter(|compounds| !compounds.is_empty())
+}
+
+fn parse_compound(text: &str) -> Option<Compound> {
+    let mut compound = Compound {
+        name: None,
+        classes: Vec::new(),
+        id: None,
+    };
+    let name_end = text.find(['.', '#']).unwrap_or(text.len());
+    match &text[..name_end] {
+        "" | "*" => {}
+        name => compound.name = Some(String::from(name)),
+    }
+
+    let mut rest = &text[name_end..];
+    while let Some(marker) = rest.chars().next() {
+        let end = rest[1..].find(['.', '#']).map_or(rest.len(), |i| i + 1);
+        let value = &rest[1..end];
+        if value.is_empty() {
+            return None;
+        }
+        match marker {
+            '.' => compound.classes.push(String::from(value)),
+            _ => compound.id = Some(String::from(value)),
+        }
+        rest = &rest[end..];
+    }
+    Some(compound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_selectors() {
+        let compound = |name: Option<&str>, classes: &[&str], id: Option<&str>| Compound {
+            name: name.map(String::from),
+            classes: classes.iter().map(|c| c.to_string()).collect(),
+            id: id.map(String::from),
+        };
+
+        assert_eq!(parse("item"), Some(vec![compound(Some("item"), &[], None)]));
+        assert_eq!(
+            parse("item.sale.new"),
+            Some(vec![compound(Some("item"), &["sale", "new"], None)])
+        );
+        assert_eq!(
+            parse("#main"),
+            Some(vec![compound(None, &[], Some("main"))])
+        );
+        assert_eq!(
+            parse(" shop  *.a#b  "),
+            Some(vec![
+                compound(Some("shop"), &[], None),
+                compound(None, &["a"], Some("b")),
+            ])
+        );
+
+        for malformed in ["", "  ", "item.", "#", "a..b", "a #"] {
+            assert_eq!(parse(malformed), None, "parsing {:?}", malformed);
+        }
+    }
+}
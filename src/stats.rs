@@ -0,0 +1,27 @@
+use crate::api::XMLNode;
+
+/// Size metrics of a document.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DocumentStats {
+    pub element_count: usize,
+    /// Elements on the longest root to leaf path, 1 for a lone root.
+    pub max_depth: usize,
+    pub attribute_count: usize,
+    /// Bytes of text content after entity decoding.
+    pub text_length: usize,
+}
+
+impl DocumentStats {
+    /// Computes the metrics of an already parsed tree. Works iteratively so
+    /// deep trees can be measured.
+    pub fn from_tree(root: &XMLNode) -> Self {
+        let mut stats = DocumentStats::default();
+        root.walk(|node, depth| {
+            stats.element_count += 1;
+            stats.max_depth = stats.max_depth.max(depth + 1);
+            stats.attribute_count += node.attribute_map_ref().len();
+            stats.text_length += node.content.borrow().len();
+        });
+        stats
+    }
+}
@@ -0,0 +1,22 @@
+/// What a stretch of the document returned by `RXML::tokens` is. A stable
+/// mirror of the lexer's own token kinds, without the parsed tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// `<name ...>`
+    StartTag,
+    /// `</name>`
+    EndTag,
+    /// `<name .../>`
+    EmptyTag,
+    Text,
+    /// A run of whitespace, however long.
+    Whitespace,
+    /// `<!-- ... -->`
+    Comment,
+    /// `<![CDATA[ ... ]]>`
+    CData,
+    /// `<!DOCTYPE ...>`, internal subset included.
+    Doctype,
+    /// `<?target ...?>`, the XML declaration among them.
+    ProcessingInstruction,
+}
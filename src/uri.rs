@@ -0,0 +1,199 @@
+/// The components of a URI reference as split by RFC 3986 appendix B.
+struct Reference<'a> {
+    scheme: Option<&'a str>,
+    authority: Option<&'a str>,
+    path: &'a str,
+    query: Option<&'a str>,
+    fragment: Option<&'a str>,
+}
+
+impl<'a> Reference<'a> {
+    fn split(text: &'a str) -> Self {
+        let (rest, fragment) = match text.split_once('#') {
+            Some((rest, fragment)) => (rest, Some(fragment)),
+            None => (text, None),
+        };
+        let (rest, query) = match rest.split_once('?') {
+            Some((rest, query)) => (rest, Some(query)),
+            None => (rest, None),
+        };
+        let (scheme, rest) = match rest.split_once(':') {
+            Some((scheme, rest)) if is_scheme(scheme) => (Some(scheme), rest),
+            _ => (None, rest),
+        };
+        let (authority, path) = match rest.strip_prefix("//") {
+            Some(rest) => {
+                let end = rest.find('/').unwrap_or(rest.len());
+                (Some(&rest[..end]), &rest[end..])
+            }
+            None => (None, rest),
+        };
+
+        Self {
+            scheme,
+            authority,
+            path,
+            query,
+            fragment,
+        }
+    }
+}
+
+fn is_scheme(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+/// Resolves `reference` against the absolute URI `base` following RFC 3986
+/// section 5.2. References that already carry a scheme are only normalized.
+pub fn resolve(base: &str, reference: &str) -> String {
+    let base = Reference::split(base);
+    let reference = Reference::split(reference);
+
+    let (scheme, authority, path, query) = if reference.scheme.is_some() {
+        (
+            reference.scheme,
+            reference.authority,
+            remove_dot_segments(reference.path),
+            reference.query,
+        )
+    } else if reference.authority.is_some() {
+        (
+            base.scheme,
+            reference.authority,
+            remove_dot_segments(reference.path),
+            reference.query,
+        )
+    } else if reference.path.is_empty() {
+        (
+            base.scheme,
+            base.authority,
+            String::from(base.path),
+            reference.query.or(base.query),
+        )
+    } else if reference.path.starts_with('/') {
+        (
+            base.scheme,
+            base.authority,
+            remove_dot_segments(reference.path),
+            reference.query,
+        )
+    } else {
+        let merged = match base.path.rfind('/') {
+            Some(slash) => format!("{}{}", &base.path[..=slash], reference.path),
+            None if base.authority.is_some() => format!("/{}", reference.path),
+            None => String::from(reference.path),
+        };
+        (
+            base.scheme,
+            base.authority,
+            remove_dot_segments(&merged),
+            reference.query,
+        )
+    };
+
+    let mut resolved = String::new();
+    if let Some(scheme) = scheme {
+        resolved.push_str(scheme);
+        resolved.push(':');
+    }
+    if let Some(authority) = authority {
+        resolved.push_str("//");
+        resolved.push_str(authority);
+    }
+    resolved.push_str(&path);
+    if let Some(query) = query {
+        resolved.push('?');
+        resolved.push_str(query);
+    }
+    if let Some(fragment) = reference.fragment {
+        resolved.push('#');
+        resolved.push_str(fragment);
+    }
+    resolved
+}
+
+/// Interprets the `.` and `..` segments of `path`, RFC 3986 section 5.2.4.
+fn remove_dot_segments(path: &str) -> String {
+    let mut output: Vec<&str> = Vec::new();
+    // The empty segment before the leading `/` of an absolute path is never
+    // removed by `..`.
+    let root = usize::from(path.starts_with('/'));
+    let segments: Vec<&str> = path.split('/').collect();
+
+    for (index, segment) in segments.iter().enumerate() {
+        let last = index == segments.len() - 1;
+        match *segment {
+            "." | ".." => {
+                if *segment == ".." && output.len() > root {
+                    output.pop();
+                }
+                // A trailing dot segment still names a directory.
+                if last {
+                    output.push("");
+                }
+            }
+            _ => output.push(segment),
+        }
+    }
+
+    let joined = output.join("/");
+    if path.starts_with('/') && !joined.starts_with('/') {
+        return format!("/{}", joined);
+    }
+    joined
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_rfc_examples() {
+        let base = "http://a/b/c/d;p?q";
+        let cases = [
+            ("g:h", "g:h"),
+            ("g", "http://a/b/c/g"),
+            ("./g", "http://a/b/c/g"),
+            ("g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            ("//g", "http://g"),
+            ("?y", "http://a/b/c/d;p?y"),
+            ("g?y", "http://a/b/c/g?y"),
+            ("#s", "http://a/b/c/d;p?q#s"),
+            ("g#s", "http://a/b/c/g#s"),
+            ("", "http://a/b/c/d;p?q"),
+            (".", "http://a/b/c/"),
+            ("./", "http://a/b/c/"),
+            ("..", "http://a/b/"),
+            ("../", "http://a/b/"),
+            ("../g", "http://a/b/g"),
+            ("../..", "http://a/"),
+            ("../../g", "http://a/g"),
+            ("../../../g", "http://a/g"),
+            ("/./g", "http://a/g"),
+            ("g.", "http://a/b/c/g."),
+            ("./../g", "http://a/b/g"),
+            ("g/./h", "http://a/b/c/g/h"),
+            ("g/../h", "http://a/b/c/h"),
+        ];
+
+        for (reference, expected) in cases {
+            assert_eq!(
+                resolve(base, reference),
+                expected,
+                "resolving {:?}",
+                reference
+            );
+        }
+    }
+
+    #[test]
+    fn test_resolve_against_authority_without_path() {
+        assert_eq!(
+            resolve("https://example.com", "img/a.png"),
+            "https://example.com/img/a.png"
+        );
+    }
+}
@@ -0,0 +1,345 @@
+use crate::api::XMLNode;
+use crate::entities;
+use std::io::{self, Write};
+
+/// Writes a document piece by piece without building a tree, escaping text
+/// and attribute values on the way out. Start and end calls must balance:
+/// `end_element` with nothing open and `finish` with elements still open both
+/// fail with `io::ErrorKind::InvalidInput`.
+pub struct EventWriter<W: Write> {
+    writer: W,
+    indent: Option<String>,
+    /// Open elements and whether each has received a child element yet.
+    open: Vec<(String, bool)>,
+    written: bool,
+    /// Nesting of inline stretches, within which no line breaks are written.
+    inline: usize,
+}
+
+impl<W: Write> EventWriter<W> {
+    /// A writer producing compact output with no added whitespace.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            indent: None,
+            open: Vec::new(),
+            written: false,
+            inline: 0,
+        }
+    }
+    /// A writer putting every element tag on its own line, nested by
+    /// `indent`. Text stays on the line of the tag before it.
+    pub fn with_indent(writer: W, indent: &str) -> Self {
+        Self {
+            indent: Some(String::from(indent)),
+            ..Self::new(writer)
+        }
+    }
+    pub fn start_element(&mut self, name: &str, attributes: &[(&str, &str)]) -> io::Result<()> {
+        self.write_open_tag(name, attributes, false)?;
+        self.open.push((String::from(name), false));
+        Ok(())
+    }
+    pub fn self_closing(&mut self, name: &str, attributes: &[(&str, &str)]) -> io::Result<()> {
+        self.write_open_tag(name, attributes, true)
+    }
+    pub fn text(&mut self, text: &str) -> io::Result<()> {
+        self.written = true;
+        self.writer
+            .write_all(entities::escape_text(text).as_bytes())
+    }
+    /// Closes the most recently started element.
+    pub fn end_element(&mut self) -> io::Result<()> {
+        let (name, has_children) = match self.open.pop() {
+            Some(element) => element,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "end_element called with no open element",
+                ));
+            }
+        };
+        if has_children {
+            self.write_line_break()?;
+        }
+        write!(self.writer, "</{}>", name)
+    }
+    /// Checks that every element was closed and hands back the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some((name, _)) = self.open.last() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("element {} was never closed", name),
+            ));
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+    fn write_open_tag(
+        &mut self,
+        name: &str,
+        attributes: &[(&str, &str)],
+        self_closing: bool,
+    ) -> io::Result<()> {
+        if let Some((_, has_children)) = self.open.last_mut() {
+            *has_children = true;
+        }
+        if self.written {
+            self.write_line_break()?;
+        }
+        self.written = true;
+
+        write!(self.writer, "<{}", name)?;
+        for (key, value) in attributes {
+            write!(
+                self.writer,
+                " {}=\"{}\"",
+                key,
+                entities::escape_attribute(value)
+            )?;
+        }
+        if self_closing {
+            self.writer.write_all(b"/>")
+        } else {
+            self.writer.write_all(b">")
+        }
+    }
+    /// Writes everything up to the matching `end_inline` without line breaks.
+    pub(crate) fn begin_inline(&mut self) {
+        self.inline += 1;
+    }
+    pub(crate) fn end_inline(&mut self) {
+        self.inline -= 1;
+    }
+    fn write_line_break(&mut self) -> io::Result<()> {
+        if self.inline > 0 {
+            return Ok(());
+        }
+        if let Some(indent) = &self.indent {
+            self.writer.write_all(b"\n")?;
+            for _ in 0..self.open.len() {
+                self.writer.write_all(indent.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes a whole tree as indented XML through an `EventWriter`. Attributes
+/// are sorted by name and elements with neither text nor children are written
+/// self-closing. Elements holding text other than whitespace are written on a
+/// single line, as indenting inside them would change their text; in other
+/// elements the whitespace between children gives way to the indentation.
+#[derive(Debug, Clone)]
+pub struct PrettyPrinter {
+    indent: String,
+    group_elements: bool,
+}
+
+impl Default for PrettyPrinter {
+    fn default() -> Self {
+        Self::with_indent("  ")
+    }
+}
+
+impl PrettyPrinter {
+    /// A printer indenting by two spaces.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_indent(indent: &str) -> Self {
+        Self {
+            indent: String::from(indent),
+            group_elements: false,
+        }
+    }
+    /// Sorts the child elements of every element by tag name, keeping
+    /// elements of the same name in document order, for documents where
+    /// element order carries no meaning. Text stays where it was: the sorted
+    /// elements fill the slots between the text runs in turn, so the text
+    /// before the first child is still written first.
+    pub fn group_elements(mut self) -> Self {
+        self.group_elements = true;
+        self
+    }
+    /// Writes `node` and its subtree to `writer`, handing it back when done.
+    pub fn write<W: Write>(&self, node: &XMLNode, writer: W) -> io::Result<W> {
+        let mut writer = EventWriter::with_indent(writer, &self.indent);
+        self.write_node(node, &mut writer)?;
+        writer.finish()
+    }
+    fn write_node<W: Write>(&self, node: &XMLNode, writer: &mut EventWriter<W>) -> io::Result<()> {
+        let attribute_map = node.attribute_map_ref();
+        let mut attributes: Vec<(&str, &str)> = attribute_map
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        attributes.sort();
+
+        let runs = node.text_runs();
+        let mut children = node.cloned_children();
+        if children.is_empty() && runs[0].is_empty() {
+            return writer.self_closing(&node.tag.name, &attributes);
+        }
+        if self.group_elements {
+            children.sort_by(|a, b| a.tag.name.cmp(&b.tag.name));
+        }
+
+        let mixed = children.is_empty() || runs.iter().any(|run| !run.trim().is_empty());
+        let write_run = |run: &str, writer: &mut EventWriter<W>| match mixed && !run.is_empty() {
+            true => writer.text(run),
+            false => Ok(()),
+        };
+
+        writer.start_element(&node.tag.name, &attributes)?;
+        if mixed {
+            writer.begin_inline();
+        }
+        for (run, child) in runs.iter().zip(children.iter()) {
+            write_run(run, writer)?;
+            self.write_node(child, writer)?;
+        }
+        write_run(&runs[runs.len() - 1], writer)?;
+        writer.end_element()?;
+        if mixed {
+            writer.end_inline();
+        }
+        Ok(())
+    }
+    pub fn print(&self, node: &XMLNode) -> String {
+        let output = self
+            .write(node, Vec::new())
+            .expect("writing to a Vec cannot fail");
+        String::from_utf8(output).expect("the tree only holds valid UTF-8")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsedoc::XMLParser;
+
+    #[test]
+    fn test_compact_document() {
+        let mut writer = EventWriter::new(Vec::new());
+        writer
+            .start_element("library", &[("name", "\"City\" & Co")])
+            .unwrap();
+        writer.start_element("book", &[("id", "1")]).unwrap();
+        writer.text("Dune <1965>").unwrap();
+        writer.end_element().unwrap();
+        writer.self_closing("shelf", &[]).unwrap();
+        writer.end_element().unwrap();
+
+        let output = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(
+            output,
+            "<library name=\"&quot;City&quot; &amp; Co\"><book id=\"1\">Dune &lt;1965&gt;</book><shelf/></library>"
+        );
+    }
+
+    #[test]
+    fn test_indented_document() {
+        let mut writer = EventWriter::with_indent(Vec::new(), "  ");
+        writer.start_element("library", &[]).unwrap();
+        writer.start_element("book", &[("id", "1")]).unwrap();
+        writer.start_element("title", &[]).unwrap();
+        writer.text("Dune").unwrap();
+        writer.end_element().unwrap();
+        writer.self_closing("isbn", &[("v", "42")]).unwrap();
+        writer.end_element().unwrap();
+        writer.start_element("book", &[]).unwrap();
+        writer.end_element().unwrap();
+        writer.end_element().unwrap();
+
+        let output = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(
+            output,
+            "<library>\n  <book id=\"1\">\n    <title>Dune</title>\n    <isbn v=\"42\"/>\n  </book>\n  <book></book>\n</library>"
+        );
+    }
+
+    #[test]
+    fn test_pretty_printer() {
+        let root =
+            XMLParser::new("<lib b='2' a='1'><book>Dune &amp; co<ch></ch></book><empty/></lib>")
+                .parse()
+                .unwrap();
+
+        assert_eq!(
+            PrettyPrinter::new().print(&root),
+            "<lib a=\"1\" b=\"2\">\n  <book>Dune &amp; co<ch/></book>\n  <empty/>\n</lib>"
+        );
+        assert_eq!(
+            PrettyPrinter::with_indent("\t").print(&root.children.borrow()[0]),
+            "<book>Dune &amp; co<ch/></book>"
+        );
+    }
+
+    #[test]
+    fn test_pretty_printer_keeps_mixed_content_inline() {
+        let root = XMLParser::new(
+            "<doc>\n <p>Hello <b>brave <i>new</i></b> world</p>\n <list> <item/> </list>\n</doc>",
+        )
+        .parse()
+        .unwrap();
+
+        assert_eq!(
+            PrettyPrinter::new().print(&root),
+            "<doc>\n  <p>Hello <b>brave <i>new</i></b> world</p>\n  <list>\n    <item/>\n  </list>\n</doc>"
+        );
+        // Whitespace alone in an element without children is kept.
+        let root = XMLParser::new("<a><b> </b></a>").parse().unwrap();
+        assert_eq!(PrettyPrinter::new().print(&root), "<a>\n  <b> </b>\n</a>");
+    }
+
+    #[test]
+    fn test_pretty_printer_groups_elements() {
+        let root = XMLParser::new(
+            "<config><server name='b'/><db/>note<server name='a'/><cache>on<x/></cache><db id='2'/></config>",
+        )
+        .parse()
+        .unwrap();
+
+        assert_eq!(
+            PrettyPrinter::new().group_elements().print(&root),
+            "<config><cache>on<x/></cache><db/>note<db id=\"2\"/><server name=\"b\"/><server name=\"a\"/></config>"
+        );
+        assert_eq!(
+            PrettyPrinter::new().print(&root),
+            "<config><server name=\"b\"/><db/>note<server name=\"a\"/><cache>on<x/></cache><db id=\"2\"/></config>"
+        );
+
+        let root =
+            XMLParser::new("<config><server name='b'/><db/><server name='a'/><cache/></config>")
+                .parse()
+                .unwrap();
+        assert_eq!(
+            PrettyPrinter::new().group_elements().print(&root),
+            "<config>\n  <cache/>\n  <db/>\n  <server name=\"b\"/>\n  <server name=\"a\"/>\n</config>"
+        );
+    }
+
+    #[test]
+    fn test_unbalanced_calls_error() {
+        let mut writer = EventWriter::new(Vec::new());
+        writer.start_element("a", &[]).unwrap();
+        writer.end_element().unwrap();
+
+        let err = writer.end_element().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let mut writer = EventWriter::new(Vec::new());
+        writer.start_element("a", &[]).unwrap();
+        writer.start_element("b", &[]).unwrap();
+        writer.end_element().unwrap();
+
+        match writer.finish() {
+            Ok(_) => panic!("Expected an error for the unclosed element"),
+            Err(e) => {
+                assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+                assert!(e.to_string().contains('a'));
+            }
+        }
+    }
+}